@@ -19,7 +19,7 @@ impl<T> Grid<T> {
         self.grid.push(row);
     }
 
-    pub fn get(&self, row: usize, col: usize) -> Option<Cell<T>> {
+    pub fn get(&self, row: usize, col: usize) -> Option<Cell<'_, T>> {
         self.grid
             .get(row)
             .and_then(|r| r.get(col))
@@ -42,7 +42,7 @@ impl<T> Grid<T> {
         self.grid.first().map(|r| r.len()).unwrap_or_default()
     }
 
-    pub fn iter_cells(&self) -> impl Iterator<Item = Cell<T>> {
+    pub fn iter_cells(&self) -> impl Iterator<Item = Cell<'_, T>> {
         GridIterator {
             r: 0,
             c: 0,
@@ -1,44 +1,268 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+};
 
-fn parse_input() -> (Vec<i32>, Vec<i32>) {
-    let input = include_str!("../inputs/01.txt");
-    let mut left: Vec<i32> = Vec::new();
-    let mut right: Vec<i32> = Vec::new();
-    input.lines().for_each(|line| {
-        let mut num = line.split_ascii_whitespace();
-        left.push(num.next().unwrap().parse().unwrap());
-        right.push(num.next().unwrap().parse().unwrap());
-    });
+// Any number of columns of numbers, filled in a row at a time so the input can be streamed in. Each
+// column keeps a count of its values as it goes, for the similarity scores.
+#[derive(Debug, Clone, Default)]
+struct Lists {
+    columns: Vec<Vec<i64>>,
+    counts: Vec<HashMap<i64, usize>>,
+}
+
+impl Lists {
+    fn read(reader: impl BufRead) -> Result<Self, String> {
+        let mut lists = Lists::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| format!("line {}: {e}", i + 1))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row = line
+                .split_ascii_whitespace()
+                .map(|num| {
+                    num.parse()
+                        .map_err(|e| format!("line {}: `{num}`: {e}", i + 1))
+                })
+                .collect::<Result<Vec<i64>, _>>()?;
+            lists
+                .push(&row)
+                .map_err(|e| format!("line {}: {e}", i + 1))?;
+        }
+        Ok(lists)
+    }
+
+    fn push(&mut self, row: &[i64]) -> Result<(), String> {
+        if self.columns.is_empty() {
+            self.columns = vec![Vec::new(); row.len()];
+            self.counts = vec![HashMap::new(); row.len()];
+        }
+        if row.len() != self.columns.len() {
+            return Err(format!(
+                "expected {} columns, found {}",
+                self.columns.len(),
+                row.len()
+            ));
+        }
+
+        for (i, &n) in row.iter().enumerate() {
+            self.columns[i].push(n);
+            *self.counts[i].entry(n).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    fn width(&self) -> usize {
+        self.columns.len()
+    }
+
+    fn sorted(&self, column: usize) -> Vec<i64> {
+        let mut sorted = self.columns[column].clone();
+        sorted.sort();
+        sorted
+    }
+
+    // Total distance between the two columns, smallest against smallest and so on up
+    fn distance(&self, a: usize, b: usize) -> i64 {
+        self.sorted(a)
+            .into_iter()
+            .zip(self.sorted(b))
+            .map(|(x, y)| (x - y).abs())
+            .sum()
+    }
+
+    // Every value in `a` times how often it turns up in `b`
+    fn similarity(&self, a: usize, b: usize) -> i64 {
+        self.columns[a]
+            .iter()
+            .map(|n| n * self.counts[b].get(n).copied().unwrap_or(0) as i64)
+            .sum()
+    }
 
-    (left, right)
+    // Spearman's rank correlation: how closely the two columns go up and down together, from -1 to 1.
+    // Tied values share the average of their ranks. There's nothing to say if either column is all
+    // one value.
+    fn rank_correlation(&self, a: usize, b: usize) -> Option<f64> {
+        let (x, y) = (ranks(&self.columns[a]), ranks(&self.columns[b]));
+        let mean = |v: &[f64]| v.iter().sum::<f64>() / v.len() as f64;
+        let (mx, my) = (mean(&x), mean(&y));
+
+        let cov: f64 = x.iter().zip(&y).map(|(x, y)| (x - mx) * (y - my)).sum();
+        let vx: f64 = x.iter().map(|x| (x - mx).powi(2)).sum();
+        let vy: f64 = y.iter().map(|y| (y - my).powi(2)).sum();
+        (vx > 0.0 && vy > 0.0).then(|| cov / (vx * vy).sqrt())
+    }
+
+    // The `top` values that turn up more often in `b` than in `a`, by how many more times, and then
+    // by value
+    fn over_represented(&self, a: usize, b: usize, top: usize) -> Vec<(i64, usize)> {
+        let mut excess = self.counts[b]
+            .iter()
+            .filter_map(|(n, &count)| {
+                let extra = count.saturating_sub(self.counts[a].get(n).copied().unwrap_or(0));
+                (extra > 0).then_some((*n, extra))
+            })
+            .collect::<Vec<_>>();
+        excess.sort_by_key(|&(n, extra)| (std::cmp::Reverse(extra), n));
+        excess.truncate(top);
+        excess
+    }
 }
 
-fn part1(input: (Vec<i32>, Vec<i32>)) -> i32 {
-    let mut left = input.0;
-    left.sort();
-    let mut right = input.1;
-    right.sort();
+// The rank of each value within its column, counting from 1
+fn ranks(column: &[i64]) -> Vec<f64> {
+    let mut order = (0..column.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| column[i]);
+
+    let mut ranks = vec![0.0; column.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end < order.len() && column[order[end]] == column[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &i in &order[start..end] {
+            ranks[i] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
 
-    left.into_iter()
-        .zip(right)
-        .fold(0, |s, (a, b)| s + a.abs_diff(b) as i32)
+fn parse_input() -> Lists {
+    parse(include_str!("../inputs/01.txt"))
 }
 
-fn part2(input: (Vec<i32>, Vec<i32>)) -> i32 {
-    let freqs: HashMap<i32, i32> = input.1.into_iter().fold(HashMap::new(), |mut h, n| {
-        let entry = h.entry(n).or_default();
-        *entry += 1;
-        h
-    });
+fn parse(input: &str) -> Lists {
+    Lists::read(input.as_bytes()).unwrap()
+}
+
+// With fewer than two columns there's nothing to pair up, as when there's no input at all
+fn part1(lists: &Lists) -> i64 {
+    if lists.width() < 2 {
+        return 0;
+    }
+    lists.distance(0, 1)
+}
 
-    input
-        .0
-        .into_iter()
-        .fold(0i32, |s, n| s + n * *freqs.get(&n).unwrap_or(&0))
+fn part2(lists: &Lists) -> i64 {
+    if lists.width() < 2 {
+        return 0;
+    }
+    lists.similarity(0, 1)
 }
 
 pub fn main() {
+    // `cargo run --bin 01 -- stats [file]` compares every pair of columns in the file, or in whatever
+    // is piped in
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("stats") {
+        let lists = match args.next() {
+            Some(path) => {
+                let file = std::fs::File::open(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+                Lists::read(BufReader::new(file))
+            }
+            None => Lists::read(std::io::stdin().lock()),
+        };
+        let lists = lists.unwrap_or_else(|e| panic!("{e}"));
+
+        for a in 0..lists.width() {
+            for b in (0..lists.width()).filter(|&b| b != a) {
+                let correlation = lists
+                    .rank_correlation(a, b)
+                    .map_or("-".to_string(), |r| format!("{r:.3}"));
+                println!(
+                    "{a} vs {b}: distance {}, similarity {}, rank correlation {correlation}, over-represented {:?}",
+                    lists.distance(a, b),
+                    lists.similarity(a, b),
+                    lists.over_represented(a, b, 5)
+                );
+            }
+        }
+        return;
+    }
+
     let input = parse_input();
-    println!("Part 1: {}", part1(input.clone()));
-    println!("Part 2: {}", part2(input.clone()));
+    println!("Part 1: {}", part1(&input));
+    println!("Part 2: {}", part2(&input));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "3   4
+4   3
+2   5
+1   3
+3   9
+3   3
+";
+
+    #[test]
+    fn test_example() {
+        let lists = parse(EXAMPLE);
+        assert_eq!(11, part1(&lists));
+        assert_eq!(31, part2(&lists));
+
+        // Neither distance nor similarity cares which way round, but over-representation does
+        assert_eq!(11, lists.distance(1, 0));
+        assert_eq!(31, lists.similarity(1, 0));
+        assert_eq!(vec![(5, 1), (9, 1)], lists.over_represented(0, 1, 5));
+        assert_eq!(vec![(5, 1)], lists.over_represented(0, 1, 1));
+        assert_eq!(vec![(1, 1), (2, 1)], lists.over_represented(1, 0, 5));
+    }
+
+    #[test]
+    fn test_columns() {
+        let lists = parse("1 10 -5\n2 20 -6\n3 30 -7\n4 40 -8\n");
+        assert_eq!(3, lists.width());
+        assert_eq!(90, lists.distance(0, 1));
+        assert_eq!(4 * 9, lists.distance(0, 2));
+        assert_eq!(Some(1.0), lists.rank_correlation(0, 1));
+        assert_eq!(Some(-1.0), lists.rank_correlation(1, 2));
+        assert_eq!(Some(1.0), lists.rank_correlation(0, 0));
+
+        // Sums that would overflow an i32
+        let lists = parse("3000000000 0\n3000000000 3000000000\n");
+        assert_eq!(3_000_000_000, lists.distance(0, 1));
+        assert_eq!(6_000_000_000, lists.similarity(0, 1));
+    }
+
+    #[test]
+    fn test_rank_correlation() {
+        // Ties share their ranks: x ranks 1, 2.5, 2.5, 4 and y ranks 1, 2, 3, 4
+        let lists = parse("1 1\n2 2\n2 3\n3 4\n");
+        let r = lists.rank_correlation(0, 1).unwrap();
+        assert!((r - 4.5 / 4.5f64.sqrt() / 5f64.sqrt()).abs() < 1e-12);
+
+        // Nothing to rank against
+        let lists = parse("1 5\n2 5\n");
+        assert_eq!(None, lists.rank_correlation(0, 1));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "line 2: expected 2 columns, found 3",
+            Lists::read("1 2\n3 4 5\n".as_bytes()).unwrap_err()
+        );
+        assert!(Lists::read("1 x\n".as_bytes())
+            .unwrap_err()
+            .starts_with("line 1: `x`"));
+        assert_eq!(0, parse("").width());
+    }
+
+    #[test]
+    fn test_empty() {
+        let lists = parse("");
+        assert_eq!(0, part1(&lists));
+        assert_eq!(0, part2(&lists));
+
+        let lists = parse("1\n2\n");
+        assert_eq!(0, part1(&lists));
+        assert_eq!(0, part2(&lists));
+    }
 }
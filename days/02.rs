@@ -1,5 +1,11 @@
+use std::ops::RangeInclusive;
+
 fn parse_input() -> Vec<Vec<i64>> {
     let input = include_str!("../inputs/02.txt");
+    parse(input)
+}
+
+fn parse(input: &str) -> Vec<Vec<i64>> {
     input
         .lines()
         .map(|line| {
@@ -10,42 +16,259 @@ fn parse_input() -> Vec<Vec<i64>> {
         .collect()
 }
 
-fn part1_safe(level: &&Vec<i64>) -> bool {
-    let diffs = level
-        .iter()
-        .zip(level.iter().skip(1))
-        .map(|(a, b)| b - a)
-        .collect::<Vec<i64>>();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Increasing,
+    Decreasing,
+}
 
-    diffs.iter().all(|d| *d >= 1 && *d <= 3) || diffs.iter().all(|d| *d >= -3 && *d <= -1)
+// How a report was made safe: which levels had to go, and which way what's left goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rescue {
+    direction: Direction,
+    dropped: Vec<usize>,
 }
 
-fn part1(input: Vec<Vec<i64>>) -> usize {
-    input.iter().filter(part1_safe).count()
+// A report is safe when every step between levels is in `steps`, all going the same way, once at
+// most `removals` levels are taken out
+struct Dampener {
+    steps: RangeInclusive<i64>,
+    removals: usize,
 }
 
-fn part2_safe(level: &&Vec<i64>) -> bool {
-    if part1_safe(level) {
-        return true;
+impl Dampener {
+    fn new(steps: RangeInclusive<i64>, removals: usize) -> Self {
+        Self { steps, removals }
     }
 
-    for i in 0..level.len() {
-        let mut l = level.to_vec();
-        l.remove(i);
-        if part1_safe(&&l) {
-            return true;
+    // The fewest levels to drop to make the report safe, if that's within the allowed removals
+    fn check(&self, report: &[i64]) -> Option<Rescue> {
+        [Direction::Increasing, Direction::Decreasing]
+            .into_iter()
+            .filter_map(|direction| {
+                let dropped = self.fewest_drops(report, direction)?;
+                Some(Rescue { direction, dropped })
+            })
+            .min_by_key(|rescue| rescue.dropped.len())
+    }
+
+    fn safe(&self, report: &[i64]) -> bool {
+        self.check(report).is_some()
+    }
+
+    // kept[i] is the fewest drops among the levels up to i for a safe run ending by keeping i, along
+    // with the level kept before it. A level can only follow one of the `removals + 1` levels just
+    // before it, so for a fixed number of removals this is linear in the length of the report.
+    fn fewest_drops(&self, report: &[i64], direction: Direction) -> Option<Vec<usize>> {
+        // An empty report has no steps to break, so it's safe as it is, like in the original check
+        if report.is_empty() {
+            return Some(Vec::new());
         }
+        let sign = match direction {
+            Direction::Increasing => 1,
+            Direction::Decreasing => -1,
+        };
+
+        let mut kept: Vec<Option<(usize, Option<usize>)>> = Vec::with_capacity(report.len());
+        for (i, level) in report.iter().enumerate() {
+            let first = (i <= self.removals).then_some((i, None));
+            let after = (i.saturating_sub(self.removals + 1)..i)
+                .filter(|j| self.steps.contains(&(sign * (level - report[*j]))))
+                .filter_map(|j| kept[j].map(|(drops, _)| (drops + i - j - 1, Some(j))))
+                .filter(|(drops, _)| *drops <= self.removals);
+            kept.push(
+                first
+                    .into_iter()
+                    .chain(after)
+                    .min_by_key(|(drops, _)| *drops),
+            );
+        }
+
+        let (last, _) = (0..report.len())
+            .filter_map(|i| kept[i].map(|(drops, _)| (i, drops + report.len() - 1 - i)))
+            .filter(|(_, drops)| *drops <= self.removals)
+            .min_by_key(|(_, drops)| *drops)?;
+
+        let mut keep = vec![false; report.len()];
+        let mut at = Some(last);
+        while let Some(i) = at {
+            keep[i] = true;
+            at = kept[i].unwrap().1;
+        }
+        Some((0..report.len()).filter(|i| !keep[*i]).collect())
     }
+}
 
-    false
+fn part1(input: Vec<Vec<i64>>) -> usize {
+    let dampener = Dampener::new(1..=3, 0);
+    input.iter().filter(|report| dampener.safe(report)).count()
 }
 
 fn part2(input: Vec<Vec<i64>>) -> usize {
-    input.iter().filter(part2_safe).count()
+    let dampener = Dampener::new(1..=3, 1);
+    input.iter().filter(|report| dampener.safe(report)).count()
 }
 
 pub fn main() {
     let input = parse_input();
-    println!("Part1: {}", part1(input.clone()));
-    println!("Part1: {}", part2(input.clone()));
+
+    // `cargo run --bin 02 -- explain <removals>` shows how each report fares with up to that many
+    // levels removed instead
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("explain") {
+        let removals = args.next().map_or(1, |k| k.parse().unwrap());
+        let dampener = Dampener::new(1..=3, removals);
+        for report in &input {
+            match dampener.check(report) {
+                Some(Rescue { direction, dropped }) => {
+                    println!("{report:?}: {direction:?} dropping {dropped:?}")
+                }
+                None => println!("{report:?}: unsafe"),
+            }
+        }
+        return;
+    }
+
+    println!("Part 1: {}", part1(input.clone()));
+    println!("Part 2: {}", part2(input.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "7 6 4 2 1
+1 2 7 8 9
+9 7 6 2 1
+1 3 2 4 5
+8 6 4 4 1
+1 3 6 7 9
+";
+
+    // Tries every way of dropping up to `removals` levels
+    fn brute_force(report: &[i64], steps: &RangeInclusive<i64>, removals: usize) -> Option<usize> {
+        (0..1u32 << report.len())
+            .filter(|mask| mask.count_ones() as usize <= removals)
+            .filter(|mask| {
+                let kept = (0..report.len())
+                    .filter(|i| mask & (1 << i) == 0)
+                    .map(|i| report[i])
+                    .collect::<Vec<_>>();
+                let diffs = kept.windows(2).map(|w| w[1] - w[0]).collect::<Vec<_>>();
+                diffs.iter().all(|d| steps.contains(d)) || diffs.iter().all(|d| steps.contains(&-d))
+            })
+            .map(|mask| mask.count_ones() as usize)
+            .min()
+    }
+
+    #[test]
+    fn test_example() {
+        let input = parse(EXAMPLE);
+        assert_eq!(2, part1(input.clone()));
+        assert_eq!(4, part2(input.clone()));
+
+        let dampener = Dampener::new(1..=3, 1);
+        let checks = input
+            .iter()
+            .map(|report| dampener.check(report))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Some(Rescue {
+                    direction: Direction::Decreasing,
+                    dropped: vec![]
+                }),
+                None,
+                None,
+                Some(Rescue {
+                    direction: Direction::Increasing,
+                    dropped: vec![2]
+                }),
+                Some(Rescue {
+                    direction: Direction::Decreasing,
+                    dropped: vec![3]
+                }),
+                Some(Rescue {
+                    direction: Direction::Increasing,
+                    dropped: vec![]
+                }),
+            ],
+            checks
+        );
+    }
+
+    #[test]
+    fn test_removals() {
+        // Dropping the first or last level, or several in a row
+        let dampener = Dampener::new(1..=3, 2);
+        assert_eq!(
+            Some(vec![0]),
+            dampener.check(&[9, 1, 2, 3]).map(|r| r.dropped)
+        );
+        assert_eq!(
+            Some(vec![3]),
+            dampener.check(&[1, 2, 3, 0]).map(|r| r.dropped)
+        );
+        assert_eq!(
+            Some(vec![2, 3]),
+            dampener.check(&[1, 2, 50, 60, 4, 5]).map(|r| r.dropped)
+        );
+        assert_eq!(None, dampener.check(&[1, 50, 2, 60, 3, 70]));
+
+        // Nothing to go wrong in an empty report or a single level
+        let none = Some(Rescue {
+            direction: Direction::Increasing,
+            dropped: vec![],
+        });
+        assert_eq!(none, Dampener::new(1..=3, 0).check(&[]));
+        assert_eq!(none, Dampener::new(1..=3, 0).check(&[7]));
+        assert_eq!(1, part1(vec![vec![]]));
+
+        // Wider steps
+        let dampener = Dampener::new(2..=10, 0);
+        assert!(dampener.safe(&[30, 20, 12, 10]));
+        assert!(!dampener.safe(&[30, 20, 19, 10]));
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        let mut seed = 0x2024_1202u64;
+        let mut next = |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+
+        for _i in 0..500 {
+            let len = 1 + next(9) as usize;
+            let report = (0..len).map(|_| next(12) as i64).collect::<Vec<_>>();
+            let removals = next(4) as usize;
+            let steps = 1..=1 + next(3) as i64;
+
+            let dampener = Dampener::new(steps.clone(), removals);
+            let rescue = dampener.check(&report);
+            assert_eq!(
+                brute_force(&report, &steps, removals),
+                rescue.as_ref().map(|r| r.dropped.len()),
+                "{report:?} with {removals} removals"
+            );
+
+            // What's left really is safe, in the direction it says
+            if let Some(Rescue { direction, dropped }) = rescue {
+                let sign = if direction == Direction::Increasing {
+                    1
+                } else {
+                    -1
+                };
+                let kept = (0..len)
+                    .filter(|i| !dropped.contains(i))
+                    .map(|i| report[i])
+                    .collect::<Vec<_>>();
+                assert!(kept
+                    .windows(2)
+                    .all(|w| steps.contains(&(sign * (w[1] - w[0])))));
+            }
+        }
+    }
 }
@@ -1,76 +1,502 @@
-use regex::Regex;
+use std::{fmt::Display, ops::Range};
 
-#[derive(Clone, Debug)]
-struct Mul {
-    a: u64,
-    b: u64,
+// What running the instructions builds up
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Machine {
+    sum: i64,
+    enabled: bool,
+    // Enabled states saved by push(), for pop() to restore
+    saved: Vec<bool>,
 }
 
-impl Mul {
-    fn total(&self) -> u64 {
-        self.a * self.b
+impl Default for Machine {
+    fn default() -> Self {
+        Self {
+            sum: 0,
+            enabled: true,
+            saved: Vec::new(),
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-enum Instr {
-    Mul(Mul),
-    Do,
-    Dont,
+// An instruction is its name followed by `arity` operands of 1 to 3 digits, separated by commas and
+// in parentheses, like `mul(2,4)` or `do()`. Arithmetic ones add to the sum while enabled.
+struct Instruction {
+    name: &'static str,
+    arity: usize,
+    arithmetic: bool,
+    effect: fn(&mut Machine, &[i64]),
 }
 
-fn parse_input() -> Vec<Instr> {
-    let re = Regex::new(r#"(?:(mul)\((\d{1,3}),(\d{1,3})\))|(do\(\))|(don't\(\))"#).unwrap();
-    let input = include_str!("../inputs/03.txt");
+const MUL: Instruction = Instruction {
+    name: "mul",
+    arity: 2,
+    arithmetic: true,
+    effect: |m, args| {
+        if m.enabled {
+            m.sum += args[0] * args[1]
+        }
+    },
+};
+
+const DO: Instruction = Instruction {
+    name: "do",
+    arity: 0,
+    arithmetic: false,
+    effect: |m, _| m.enabled = true,
+};
+
+const DONT: Instruction = Instruction {
+    name: "don't",
+    arity: 0,
+    arithmetic: false,
+    effect: |m, _| m.enabled = false,
+};
 
-    re.captures_iter(input)
-        .map(|caps| match &caps[0] {
-            "do()" => Instr::Do,
-            "don't()" => Instr::Dont,
-            _ if caps[0].starts_with("mul") => Instr::Mul(Mul {
-                a: caps[2].parse::<u64>().unwrap(),
-                b: caps[3].parse::<u64>().unwrap(),
-            }),
-            _ => panic!("unknown cap {}", &caps[0]),
-        })
-        .collect()
+const ADD: Instruction = Instruction {
+    name: "add",
+    arity: 2,
+    arithmetic: true,
+    effect: |m, args| {
+        if m.enabled {
+            m.sum += args[0] + args[1]
+        }
+    },
+};
+
+const SUB: Instruction = Instruction {
+    name: "sub",
+    arity: 2,
+    arithmetic: true,
+    effect: |m, args| {
+        if m.enabled {
+            m.sum += args[0] - args[1]
+        }
+    },
+};
+
+const PUSH: Instruction = Instruction {
+    name: "push",
+    arity: 0,
+    arithmetic: false,
+    effect: |m, _| m.saved.push(m.enabled),
+};
+
+const POP: Instruction = Instruction {
+    name: "pop",
+    arity: 0,
+    arithmetic: false,
+    effect: |m, _| {
+        if let Some(enabled) = m.saved.pop() {
+            m.enabled = enabled
+        }
+    },
+};
+
+// The instructions the scanner knows about. Where one name starts another, like `do` and `don't`,
+// the longest one that fits wins.
+struct Registry(Vec<Instruction>);
+
+impl Registry {
+    fn part1() -> Self {
+        Self(vec![MUL])
+    }
+
+    fn part2() -> Self {
+        Self(vec![MUL, DO, DONT])
+    }
+
+    // Part 2 along with add(a,b), sub(a,b), and push()/pop() to save and restore whether
+    // instructions are enabled
+    fn extended() -> Self {
+        Self(vec![MUL, DO, DONT, ADD, SUB, PUSH, POP])
+    }
+
+    fn find(&self, memory: &str) -> Option<&Instruction> {
+        self.0
+            .iter()
+            .filter(|i| memory.starts_with(i.name))
+            .max_by_key(|i| i.name.len())
+    }
+}
+
+// Why something that started like an instruction isn't one
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Reason {
+    Expected { expected: char, found: Option<char> },
+    Whitespace,
+    MissingOperand,
+    TooManyDigits(String),
+}
+
+impl Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Expected {
+                expected,
+                found: Some(found),
+            } => write!(f, "expected `{expected}` but found `{found}`"),
+            Reason::Expected {
+                expected,
+                found: None,
+            } => write!(f, "expected `{expected}` but the memory ended"),
+            Reason::Whitespace => write!(f, "instructions can't contain whitespace"),
+            Reason::MissingOperand => write!(f, "missing operand"),
+            Reason::TooManyDigits(n) => write!(f, "operand `{n}` has more than 3 digits"),
+        }
+    }
 }
 
-fn part1(input: Vec<Instr>) -> u64 {
-    input
-        .into_iter()
-        .filter_map(|a| {
-            if let Instr::Mul(mul) = a {
-                Some(mul.total())
-            } else {
-                None
+// What the scanner found in the memory, and where
+enum Scanned<'a> {
+    Instr(&'a Instruction, Vec<i64>, Range<usize>),
+    NearMiss(Reason, Range<usize>),
+}
+
+// Walks through the memory looking for instructions. Anything that starts with an instruction's name
+// but doesn't go on to be one is reported as a near miss, up to and including the character that gave
+// it away, and scanning picks up again right after where it started.
+// Everything it steps over apart from the memory's own characters is ASCII, so positions always stay
+// on character boundaries.
+struct Scanner<'a> {
+    registry: &'a Registry,
+    memory: &'a str,
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(registry: &'a Registry, memory: &'a str) -> Self {
+        Self {
+            registry,
+            memory,
+            pos: 0,
+        }
+    }
+
+    fn char_at(&self, at: usize) -> Option<char> {
+        self.memory.get(at..)?.chars().next()
+    }
+
+    fn expect(&self, at: &mut usize, expected: char) -> Result<(), (Reason, usize)> {
+        match self.char_at(*at) {
+            Some(ch) if ch == expected => {
+                *at += 1;
+                Ok(())
+            }
+            Some(ch) if ch.is_ascii_whitespace() => Err((Reason::Whitespace, *at)),
+            found => Err((Reason::Expected { expected, found }, *at)),
+        }
+    }
+
+    fn number(&self, at: &mut usize) -> Result<i64, (Reason, usize)> {
+        let start = *at;
+        while self.char_at(*at).is_some_and(|ch| ch.is_ascii_digit()) {
+            *at += 1;
+        }
+
+        let digits = &self.memory[start..*at];
+        match digits.len() {
+            0 if self.char_at(*at).is_some_and(|ch| ch.is_ascii_whitespace()) => {
+                Err((Reason::Whitespace, *at))
+            }
+            0 => Err((Reason::MissingOperand, *at)),
+            1..=3 => Ok(digits.parse().unwrap()),
+            _ => Err((Reason::TooManyDigits(digits.to_string()), *at - 1)),
+        }
+    }
+
+    // The operands of `instr` from `at` on, and where they end, or why there aren't any and where that
+    // became clear
+    fn operands(
+        &self,
+        instr: &Instruction,
+        mut at: usize,
+    ) -> Result<(Vec<i64>, usize), (Reason, usize)> {
+        self.expect(&mut at, '(')?;
+        let mut args = Vec::with_capacity(instr.arity);
+        for i in 0..instr.arity {
+            if i > 0 {
+                self.expect(&mut at, ',')?;
             }
-        })
-        .sum()
+            args.push(self.number(&mut at)?);
+        }
+        self.expect(&mut at, ')')?;
+        Ok((args, at))
+    }
 }
 
-fn part2(input: Vec<Instr>) -> u64 {
-    let mut enabled = true;
-    let mut sum = 0;
+impl<'a> Iterator for Scanner<'a> {
+    type Item = Scanned<'a>;
 
-    for instr in input {
-        match instr {
-            Instr::Mul(mul) => {
-                if enabled {
-                    sum += mul.total()
+    fn next(&mut self) -> Option<Scanned<'a>> {
+        while self.pos < self.memory.len() {
+            let start = self.pos;
+            let Some(instr) = self.registry.find(&self.memory[start..]) else {
+                self.pos += self.char_at(start).map_or(1, char::len_utf8);
+                continue;
+            };
+
+            match self.operands(instr, start + instr.name.len()) {
+                Ok((args, end)) => {
+                    self.pos = end;
+                    return Some(Scanned::Instr(instr, args, start..end));
+                }
+                Err((reason, at)) => {
+                    // Instruction names are ASCII, so this is the next character along
+                    self.pos += 1;
+                    let end = at + self.char_at(at).map_or(0, char::len_utf8);
+                    return Some(Scanned::NearMiss(reason, start..end));
                 }
             }
-            Instr::Do => {
-                enabled = true;
-            }
-            Instr::Dont => enabled = false,
         }
+
+        None
+    }
+}
+
+// Runs every instruction in the registry that turns up in the memory, in one pass
+fn run(registry: &Registry, memory: &str) -> Machine {
+    let mut machine = Machine::default();
+    for scanned in Scanner::new(registry, memory) {
+        if let Scanned::Instr(instr, args, _) = scanned {
+            (instr.effect)(&mut machine, &args);
+        }
+    }
+    machine
+}
+
+fn parse_input() -> &'static str {
+    include_str!("../inputs/03.txt")
+}
+
+// A line for everything the scanner found, saying what it did to the machine, followed by the
+// stretches of memory that were enabled and disabled
+fn annotate(registry: &Registry, memory: &str) -> String {
+    let mut lines = Vec::new();
+    let mut machine = Machine::default();
+    let mut regions = vec![(true, 0)];
+    for scanned in Scanner::new(registry, memory) {
+        let (span, what) = match scanned {
+            Scanned::Instr(instr, args, span) => {
+                let before = machine.clone();
+                (instr.effect)(&mut machine, &args);
+
+                let what = if instr.arithmetic && before.enabled {
+                    format!("{:+} = {}", machine.sum - before.sum, machine.sum)
+                } else if instr.arithmetic {
+                    "skipped, disabled".to_string()
+                } else if machine.enabled != before.enabled {
+                    regions.push((machine.enabled, span.start));
+                    (if machine.enabled {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    })
+                    .to_string()
+                } else if machine.saved.len() > before.saved.len() {
+                    "saved".to_string()
+                } else if machine.saved.len() < before.saved.len() {
+                    "restored".to_string()
+                } else {
+                    "no effect".to_string()
+                };
+                (span, what)
+            }
+            Scanned::NearMiss(reason, span) => (span, format!("ignored, {reason}")),
+        };
+        lines.push(format!(
+            "{:>6}..{:<6} {:<16} {what}",
+            span.start,
+            span.end,
+            &memory[span.clone()]
+        ));
     }
 
-    sum
+    regions.push((machine.enabled, memory.len()));
+    for pair in regions.windows(2) {
+        let state = if pair[0].0 { "enabled" } else { "disabled" };
+        lines.push(format!("{state} {}..{}", pair[0].1, pair[1].1));
+    }
+
+    lines.join("\n")
+}
+
+fn part1(input: &str) -> i64 {
+    run(&Registry::part1(), input).sum
+}
+
+fn part2(input: &str) -> i64 {
+    run(&Registry::part2(), input).sum
 }
+
 pub fn main() {
     let input = parse_input();
-    println!("Part 1: {}", part1(input.clone()));
-    println!("Part 1: {}", part2(input.clone()));
+
+    // `cargo run --bin 03 -- annotate [extended]` explains how part 2's sum comes about instead, or
+    // the sum with the extended instruction set
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("annotate") {
+        let registry = if args.next().as_deref() == Some("extended") {
+            Registry::extended()
+        } else {
+            Registry::part2()
+        };
+        println!("{}", annotate(&registry, input));
+        return;
+    }
+
+    println!("Part 1: {}", part1(input));
+    println!("Part 2: {}", part2(input));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE_1: &str =
+        "xmul(2,4)%&mul[3,7]!@^do_not_mul(5,5)+mul(32,64]then(mul(11,8)mul(8,5))";
+    const EXAMPLE_2: &str =
+        "xmul(2,4)&mul[3,7]!^don't()_mul(5,5)+mul(32,64](mul(11,8)undo()?mul(8,5))";
+
+    fn instrs<'a>(registry: &'a Registry, memory: &str) -> Vec<(&'a str, Vec<i64>, Range<usize>)> {
+        Scanner::new(registry, memory)
+            .filter_map(|scanned| match scanned {
+                Scanned::Instr(instr, args, span) => Some((instr.name, args, span)),
+                Scanned::NearMiss(..) => None,
+            })
+            .collect()
+    }
+
+    fn near_misses(memory: &str) -> Vec<(String, &str)> {
+        Scanner::new(&Registry::part2(), memory)
+            .filter_map(|scanned| match scanned {
+                Scanned::NearMiss(reason, span) => Some((reason.to_string(), &memory[span])),
+                Scanned::Instr(..) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_example() {
+        assert_eq!(161, part1(EXAMPLE_1));
+        assert_eq!(48, part2(EXAMPLE_2));
+
+        // Part 1 doesn't know about do() and don't()
+        assert_eq!(161, part1(EXAMPLE_2));
+    }
+
+    #[test]
+    fn test_spans() {
+        let registry = Registry::part2();
+        let instrs = instrs(&registry, EXAMPLE_2);
+        assert_eq!(6, instrs.len());
+        assert_eq!(("mul", vec![2, 4], 1..9), instrs[0]);
+        assert_eq!(("don't", vec![], 20..27), instrs[1]);
+        for (_, _, span) in &instrs {
+            assert!(EXAMPLE_2[span.clone()].ends_with(')'));
+        }
+    }
+
+    #[test]
+    fn test_near_misses() {
+        assert_eq!(
+            vec![
+                ("expected `(` but found `[`".to_string(), "mul["),
+                ("expected `(` but found `_`".to_string(), "do_"),
+                ("expected `)` but found `]`".to_string(), "mul(32,64]"),
+            ],
+            near_misses(EXAMPLE_1)
+        );
+
+        assert_eq!(
+            vec![("expected `,` but found `*`".to_string(), "mul(4*")],
+            near_misses("mul(4*")
+        );
+        assert_eq!(
+            vec![("instructions can't contain whitespace".to_string(), "mul ")],
+            near_misses("mul ( 2 , 4 )")
+        );
+        assert_eq!(
+            vec![(
+                "operand `1234` has more than 3 digits".to_string(),
+                "mul(1234"
+            )],
+            near_misses("mul(1234,5)")
+        );
+        assert_eq!(
+            vec![
+                ("missing operand".to_string(), "mul(,"),
+                ("expected `(` but the memory ended".to_string(), "don't"),
+            ],
+            near_misses("mul(,3)don't")
+        );
+
+        // A broken instruction doesn't hide one starting inside it
+        assert_eq!(
+            vec![("mul", vec![2, 3], 4..12)],
+            instrs(&Registry::part1(), "mul(mul(2,3)")
+        );
+    }
+
+    #[test]
+    fn test_extended() {
+        let registry = Registry::extended();
+        assert_eq!(
+            2 * 3 + (4 + 5) + (1 - 7),
+            run(&registry, "mul(2,3)add(4,5)?sub(1,7)").sum
+        );
+        assert_eq!(
+            vec![("add", vec![1, 2], 0..8)],
+            instrs(&Registry::extended(), "add(1,2)add(3)")
+        );
+
+        // Scopes restore whatever was enabled before them, however deep they go
+        let memory = "don't()push()do()mul(2,3)push()don't()mul(9,9)pop()add(1,1)pop()mul(4,5)";
+        let machine = run(&registry, memory);
+        assert_eq!(6 + 2, machine.sum);
+        assert!(!machine.enabled);
+        assert!(machine.saved.is_empty());
+
+        // Popping more than was pushed leaves things be
+        assert_eq!(7, run(&registry, "pop()pop()add(3,4)").sum);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let annotated = annotate(&Registry::part2(), EXAMPLE_2);
+        let lines = annotated.lines().collect::<Vec<_>>();
+        assert!(lines[0].contains("mul(2,4)") && lines[0].ends_with("+8 = 8"));
+        assert!(lines
+            .iter()
+            .any(|l| l.contains("mul(5,5)") && l.ends_with("skipped, disabled")));
+        assert!(lines.iter().any(|l| l.ends_with("+40 = 48")));
+        assert_eq!(
+            vec!["enabled 0..20", "disabled 20..59", "enabled 59..73"],
+            lines[lines.len() - 3..]
+        );
+
+        // Multiplying by 0 still counts, and don't() when already disabled changes nothing
+        let annotated = annotate(&Registry::extended(), "mul(0,5)push()don't()don't()pop()");
+        let whats = annotated.lines().collect::<Vec<_>>();
+        assert!(whats[0].ends_with("+0 = 0"), "{annotated}");
+        assert!(whats[1].ends_with("saved"), "{annotated}");
+        assert!(whats[2].ends_with("disabled"), "{annotated}");
+        assert!(whats[3].ends_with("no effect"), "{annotated}");
+        assert!(whats[4].ends_with("enabled"), "{annotated}");
+    }
+
+    #[test]
+    fn test_unicode() {
+        let memory = "é mul(2,3)ü mul(4,é)mul(2ü mul(1,1)é";
+        assert_eq!(2, instrs(&Registry::part1(), memory).len());
+        assert_eq!(
+            vec![
+                ("missing operand".to_string(), "mul(4,é"),
+                ("expected `,` but found `ü`".to_string(), "mul(2ü"),
+            ],
+            near_misses(memory)
+        );
+        assert_eq!(2 * 3 + 1, part1(memory));
+        assert!(annotate(&Registry::part2(), memory).contains("mul(4,é "));
+    }
 }
@@ -1,4 +1,8 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet, VecDeque},
+    fmt::Display,
+};
 
 #[derive(Clone, Debug)]
 struct Input {
@@ -7,7 +11,10 @@ struct Input {
 }
 
 fn parse_input() -> Input {
-    let input = include_str!("../inputs/05.txt");
+    parse(include_str!("../inputs/05.txt"))
+}
+
+fn parse(input: &str) -> Input {
     let mut splits = input.split("\n\n");
     let mut rules = Vec::new();
 
@@ -20,7 +27,7 @@ fn parse_input() -> Input {
     }
 
     let mut updates = Vec::new();
-    for line in splits.next().unwrap().lines() {
+    for line in splits.next().unwrap_or_default().lines() {
         updates.push(
             line.split(",")
                 .map(|page| page.parse())
@@ -32,83 +39,532 @@ fn parse_input() -> Input {
     Input { rules, updates }
 }
 
-fn respects_part1(update: &[u32], rules: &[(u32, u32)]) -> bool {
-    let mut rules_h: HashMap<u32, HashSet<u32>> = HashMap::new();
-    for (before, after) in rules {
-        let entry = rules_h.entry(*after).or_default();
-        entry.insert(*before);
+// Rules that can't all hold at once: each page has to come before the next, and the last before the
+// first
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cycle(Vec<u32>);
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, page) in self.0.iter().enumerate() {
+            let next = self.0[(i + 1) % self.0.len()];
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{page}|{next}")?;
+        }
+        Ok(())
     }
+}
 
-    for (i, num) in update.iter().enumerate() {
-        let Some(must_be_before) = rules_h.get(num) else {
-            continue;
-        };
+// A rule `before|later` the update breaks, and where the two pages are in it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Violation {
+    rule: (u32, u32),
+    positions: (usize, usize),
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ((before, later), (i, j)) = (self.rule, self.positions);
+        write!(
+            f,
+            "{before}|{later}: {before} is at {i}, after {later} at {j}"
+        )
+    }
+}
+
+// Why an update is out of order, and the fewest pages that would have to be moved elsewhere in it to
+// put that right, if the rules can be kept at all
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Report {
+    update: Vec<u32>,
+    violations: Vec<Violation>,
+    moves: Result<usize, Cycle>,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pages = self.update.iter().map(u32::to_string).collect::<Vec<_>>();
+        write!(f, "{}", pages.join(","))?;
+        if self.violations.is_empty() {
+            return write!(f, ": valid");
+        }
+        for violation in &self.violations {
+            write!(f, "\n  {violation}")?;
+        }
+        match &self.moves {
+            Ok(1) => write!(f, "\n  1 move to fix"),
+            Ok(moves) => write!(f, "\n  {moves} moves to fix"),
+            Err(cycle) => write!(f, "\n  can't be fixed: {cycle}"),
+        }
+    }
+}
+
+// The ordering rules, by the page that has to come first. Pages in an update are assumed to be
+// distinct, and a rule only matters when both of its pages are in the update.
+struct RuleSet {
+    after: HashMap<u32, Vec<u32>>,
+}
+
+impl RuleSet {
+    fn new(rules: &[(u32, u32)]) -> Self {
+        let mut after: HashMap<u32, Vec<u32>> = HashMap::new();
+        for &(before, later) in rules {
+            after.entry(before).or_default().push(later);
+        }
+        Self { after }
+    }
+
+    fn later(&self, page: u32) -> &[u32] {
+        self.after.get(&page).map_or(&[], |pages| pages)
+    }
+
+    // Only looks at the rules for the pages in the update, rather than every pair of pages
+    fn valid(&self, update: &[u32]) -> bool {
+        let positions = positions(update);
+        update.iter().enumerate().all(|(i, page)| {
+            self.later(*page)
+                .iter()
+                .all(|later| positions.get(later).is_none_or(|j| i < *j))
+        })
+    }
+
+    // The pages of the update in an order that keeps to the rules, changing as little as it can:
+    // whenever there's a choice, the page that came first in the update goes first
+    fn order(&self, update: &[u32]) -> Result<Vec<u32>, Cycle> {
+        let positions = positions(update);
+        let mut waiting = vec![0; update.len()];
+        for page in update {
+            for later in self.later(*page) {
+                if let Some(&j) = positions.get(later) {
+                    waiting[j] += 1;
+                }
+            }
+        }
+
+        let mut ready = (0..update.len())
+            .filter(|i| waiting[*i] == 0)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut ordered = Vec::with_capacity(update.len());
+        while let Some(Reverse(i)) = ready.pop() {
+            ordered.push(update[i]);
+            for later in self.later(update[i]) {
+                if let Some(&j) = positions.get(later) {
+                    waiting[j] -= 1;
+                    if waiting[j] == 0 {
+                        ready.push(Reverse(j));
+                    }
+                }
+            }
+        }
+
+        if ordered.len() == update.len() {
+            return Ok(ordered);
+        }
+        let stuck = (0..update.len())
+            .filter(|i| waiting[*i] > 0)
+            .map(|i| update[i])
+            .collect::<HashSet<_>>();
+        Err(self.shortest_cycle(update, &stuck))
+    }
+
+    // Every rule the update breaks, by where its pages are
+    fn violations(&self, update: &[u32]) -> Vec<Violation> {
+        let positions = positions(update);
+        let mut violations = Vec::new();
+        for (i, page) in update.iter().enumerate() {
+            for later in self.later(*page) {
+                if let Some(&j) = positions.get(later).filter(|j| **j < i) {
+                    violations.push(Violation {
+                        rule: (*page, *later),
+                        positions: (i, j),
+                    });
+                }
+            }
+        }
+        violations.sort_by_key(|v| (v.positions.1, v.positions.0));
+        violations
+    }
+
+    fn explain(&self, update: &[u32]) -> Report {
+        Report {
+            update: update.to_vec(),
+            violations: self.violations(update),
+            moves: self.order(update).map(|_| self.moves(update)),
+        }
+    }
+
+    // The pages left where they are can't have any pair the rules, followed from page to page, want
+    // the other way round. Those pairs make a partial order, so the most pages that can stay is its
+    // largest antichain, which by Dilworth's theorem leaves as many to move as there are pairs in a
+    // maximum matching of it. Only called once the rules are known to have no cycle.
+    fn moves(&self, update: &[u32]) -> usize {
+        let positions = positions(update);
+        let n = update.len();
 
-        for x in &update[i + 1..] {
-            if must_be_before.contains(x) {
-                return false;
+        // before[i][j] when page i has to come somewhere before page j
+        let mut before = vec![vec![false; n]; n];
+        for (i, row) in before.iter_mut().enumerate() {
+            let mut stack = vec![i];
+            while let Some(k) = stack.pop() {
+                for later in self.later(update[k]) {
+                    if let Some(&j) = positions.get(later).filter(|j| !row[**j]) {
+                        row[j] = true;
+                        stack.push(j);
+                    }
+                }
             }
         }
+
+        // Pairs out of order: i is earlier in the update but j has to come first
+        let reversed = (0..n)
+            .map(|i| (i + 1..n).filter(|j| before[*j][i]).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        let mut matched: Vec<Option<usize>> = vec![None; n];
+        (0..n)
+            .filter(|i| augment(*i, &reversed, &mut matched, &mut vec![false; n]))
+            .count()
+    }
+
+    // Every page left over once nothing else can be placed is on or behind a cycle. The shortest
+    // cycle through each of them is found by a breadth first search back round to itself.
+    fn shortest_cycle(&self, update: &[u32], stuck: &HashSet<u32>) -> Cycle {
+        let mut best: Option<Vec<u32>> = None;
+        for &start in update.iter().filter(|page| stuck.contains(page)) {
+            let mut prev: HashMap<u32, u32> = HashMap::new();
+            let mut queue = VecDeque::from([start]);
+            'search: while let Some(page) = queue.pop_front() {
+                for &later in self.later(page) {
+                    if !stuck.contains(&later) || prev.contains_key(&later) {
+                        continue;
+                    }
+                    prev.insert(later, page);
+                    if later == start {
+                        break 'search;
+                    }
+                    queue.push_back(later);
+                }
+            }
+
+            let Some(&last) = prev.get(&start) else {
+                continue;
+            };
+            let mut cycle = vec![last];
+            while *cycle.last().unwrap() != start {
+                cycle.push(prev[cycle.last().unwrap()]);
+            }
+            cycle.reverse();
+            if best.as_ref().is_none_or(|best| cycle.len() < best.len()) {
+                best = Some(cycle);
+            }
+        }
+        Cycle(best.expect("pages left over without a cycle"))
+    }
+}
+
+// Looks for a path from `i` that can take one more pair into the matching
+fn augment(
+    i: usize,
+    edges: &[Vec<usize>],
+    matched: &mut [Option<usize>],
+    seen: &mut [bool],
+) -> bool {
+    for &j in &edges[i] {
+        if seen[j] {
+            continue;
+        }
+        seen[j] = true;
+        if matched[j].is_none_or(|k| augment(k, edges, matched, seen)) {
+            matched[j] = Some(i);
+            return true;
+        }
     }
+    false
+}
 
-    true
+fn positions(update: &[u32]) -> HashMap<u32, usize> {
+    update
+        .iter()
+        .enumerate()
+        .map(|(i, page)| (*page, i))
+        .collect()
 }
 
 fn part1(input: Input) -> u32 {
+    let rules = RuleSet::new(&input.rules);
     input
         .updates
         .iter()
-        .filter_map(|update| {
-            if respects_part1(update, &input.rules) {
-                Some(update[update.len() / 2])
-            } else {
-                None
-            }
-        })
+        .filter(|update| rules.valid(update))
+        .map(|update| update[update.len() / 2])
         .sum()
 }
 
-fn part2(input: Input) -> u32 {
-    let bad_updates = input
-        .updates
-        .iter()
-        .filter(|update| !respects_part1(update, &input.rules));
-
+fn part2(input: Input) -> Result<u32, Cycle> {
+    let rules = RuleSet::new(&input.rules);
     let mut sum = 0;
-    let mut rules_h: HashMap<u32, HashSet<u32>> = HashMap::new();
-    for (before, after) in input.rules.iter() {
-        let entry = rules_h.entry(*after).or_default();
-        entry.insert(*before);
-    }
-    'outer: for update in bad_updates {
-        let mut new = Vec::new();
-        let mut candidates = update.to_vec();
-
-        while !candidates.is_empty() {
-            if let Some(i) = (0..candidates.len()).find(|i| {
-                rules_h.get(&candidates[*i]).is_none_or(|before| {
-                    before
-                        .iter()
-                        .all(|bf| new.iter().any(|x| bf == x) || !candidates.contains(bf))
-                })
-            }) {
-                let num = candidates.remove(i);
-                if new.len() == update.len() / 2 {
-                    sum += num;
-                    continue 'outer;
-                }
-                new.push(num);
-            } else {
-                panic!("{:?}", candidates);
-            }
-        }
+    for update in input.updates.iter().filter(|update| !rules.valid(update)) {
+        let ordered = rules.order(update)?;
+        sum += ordered[ordered.len() / 2];
     }
-
-    sum
+    Ok(sum)
 }
 
 pub fn main() {
     let input = parse_input();
+
+    // `cargo run --bin 05 -- explain [X|Y ...]` says why each update is out of order. Given extra
+    // rules, it only reports the updates that were fine before them and aren't any more.
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("explain") {
+        let extra = args.collect::<Vec<_>>().join("\n");
+        let extra = parse(&extra).rules;
+        let old = RuleSet::new(&input.rules);
+        let rules = RuleSet::new(&[input.rules, extra.clone()].concat());
+        for update in &input.updates {
+            if !rules.valid(update) && (extra.is_empty() || old.valid(update)) {
+                println!("{}", rules.explain(update));
+            }
+        }
+        return;
+    }
+
     println!("Part 1: {}", part1(input.clone()));
-    println!("Part 2: {}", part2(input.clone()));
+    match part2(input.clone()) {
+        Ok(sum) => println!("Part 2: {sum}"),
+        Err(cycle) => println!("Part 2: rules can't be kept: {cycle}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "47|53
+97|13
+97|61
+97|47
+75|29
+61|13
+75|53
+29|13
+97|29
+53|29
+61|53
+97|53
+61|29
+47|13
+75|47
+97|75
+47|61
+75|61
+47|29
+75|13
+53|13
+
+75,47,61,53,29
+97,61,53,29,13
+75,29,13
+75,97,47,61,53
+61,13,29
+97,13,75,29,47
+";
+
+    #[test]
+    fn test_example() {
+        let input = parse(EXAMPLE);
+        assert_eq!(143, part1(input.clone()));
+        assert_eq!(Ok(123), part2(input.clone()));
+
+        let rules = RuleSet::new(&input.rules);
+        let valid = input
+            .updates
+            .iter()
+            .map(|update| rules.valid(update))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![true, true, true, false, false, false], valid);
+
+        assert_eq!(Ok(vec![97, 75, 47, 61, 53]), rules.order(&input.updates[3]));
+        assert_eq!(Ok(vec![61, 29, 13]), rules.order(&input.updates[4]));
+        assert_eq!(Ok(vec![97, 75, 47, 29, 13]), rules.order(&input.updates[5]));
+        for update in &input.updates[..3] {
+            assert_eq!(Ok(update.clone()), rules.order(update));
+        }
+    }
+
+    #[test]
+    fn test_unrelated_pages() {
+        // With nothing to say otherwise pages stay where they were
+        let rules = RuleSet::new(&[(1, 2)]);
+        assert!(rules.valid(&[5, 1, 4, 2, 3]));
+        assert_eq!(Ok(vec![5, 4, 1, 2, 3]), rules.order(&[5, 2, 4, 1, 3]));
+        assert_eq!(Ok(vec![1, 2]), rules.order(&[2, 1]));
+    }
+
+    // Every way of putting the update in order, and how many pages it moves: all those not in the
+    // longest run of pages left in the same order
+    fn fewest_moves(rules: &RuleSet, update: &[u32]) -> usize {
+        fn orders(pages: Vec<u32>) -> Vec<Vec<u32>> {
+            if pages.is_empty() {
+                return vec![vec![]];
+            }
+            let mut all = Vec::new();
+            for i in 0..pages.len() {
+                let mut rest = pages.clone();
+                let page = rest.remove(i);
+                for mut order in orders(rest) {
+                    order.insert(0, page);
+                    all.push(order);
+                }
+            }
+            all
+        }
+
+        let common = |a: &[u32], b: &[u32]| {
+            let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in 0..a.len() {
+                for j in 0..b.len() {
+                    lcs[i + 1][j + 1] = if a[i] == b[j] {
+                        lcs[i][j] + 1
+                    } else {
+                        lcs[i][j + 1].max(lcs[i + 1][j])
+                    };
+                }
+            }
+            lcs[a.len()][b.len()]
+        };
+
+        orders(update.to_vec())
+            .into_iter()
+            .filter(|order| rules.valid(order))
+            .map(|order| update.len() - common(update, &order))
+            .min()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_explain() {
+        let input = parse(EXAMPLE);
+        let rules = RuleSet::new(&input.rules);
+
+        let report = rules.explain(&input.updates[3]);
+        assert_eq!(
+            vec![Violation {
+                rule: (97, 75),
+                positions: (1, 0)
+            }],
+            report.violations
+        );
+        assert_eq!(Ok(1), report.moves);
+        assert_eq!(
+            "75,97,47,61,53\n  97|75: 97 is at 1, after 75 at 0\n  1 move to fix",
+            report.to_string()
+        );
+
+        // 13 goes to the end, and one of 29 and 47 to the other side
+        let report = rules.explain(&input.updates[5]);
+        assert_eq!(Ok(2), report.moves);
+        assert_eq!(
+            vec![(75, 13), (29, 13), (47, 13), (47, 29)],
+            report.violations.iter().map(|v| v.rule).collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            "75,29,13: valid",
+            rules.explain(&input.updates[2]).to_string()
+        );
+
+        // A rule against the others breaks every update with both its pages, and moving pages won't help
+        let mut input = parse(EXAMPLE);
+        input.rules.push((29, 61));
+        let rules = RuleSet::new(&input.rules);
+        let broken = input
+            .updates
+            .iter()
+            .filter(|update| !rules.valid(update))
+            .map(|update| rules.explain(update))
+            .collect::<Vec<_>>();
+        assert_eq!(5, broken.len());
+        assert_eq!(Err(Cycle(vec![61, 29])), broken[0].moves);
+
+        // Turning a rule round instead fixes one update and breaks others
+        let mut input = parse(EXAMPLE);
+        let turned = input.rules.iter().position(|r| *r == (97, 75)).unwrap();
+        input.rules[turned] = (75, 97);
+        let rules = RuleSet::new(&input.rules);
+        assert!(rules.valid(&input.updates[3]));
+        let report = rules.explain(&[97, 47, 75]);
+        assert_eq!(Ok(1), report.moves);
+        assert_eq!(
+            vec![(75, 97), (75, 47)],
+            report.violations.iter().map(|v| v.rule).collect::<Vec<_>>()
+        );
+
+        let rules = RuleSet::new(&[(1, 2), (2, 1)]);
+        assert_eq!(
+            "2,1\n  1|2: 1 is at 1, after 2 at 0\n  can't be fixed: 2|1, 1|2",
+            rules.explain(&[2, 1]).to_string()
+        );
+    }
+
+    #[test]
+    fn test_moves_against_brute_force() {
+        let mut seed = 0x2024_1205u64;
+        let mut next = |n: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % n
+        };
+
+        for _i in 0..200 {
+            // Rules that all agree with some hidden order of the pages, so there's always a way
+            let pages = 1 + next(6) as u32;
+            let mut rules = Vec::new();
+            for a in 0..pages {
+                for b in a + 1..pages {
+                    if next(3) == 0 {
+                        rules.push((a, b));
+                    }
+                }
+            }
+            let rules = RuleSet::new(&rules);
+
+            let mut update = (0..pages).collect::<Vec<_>>();
+            for i in (1..update.len()).rev() {
+                update.swap(i, next(i as u64 + 1) as usize);
+            }
+
+            let report = rules.explain(&update);
+            assert_eq!(
+                Ok(fewest_moves(&rules, &update)),
+                report.moves,
+                "{update:?}"
+            );
+            assert_eq!(rules.valid(&update), report.violations.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        // 1|2|3|4 goes round, but 2|4|1 is a shorter way round; 5 is only stuck behind it
+        let rules = RuleSet::new(&[(1, 2), (2, 3), (3, 4), (4, 1), (2, 4), (4, 5), (6, 1)]);
+        let cycle = rules.order(&[6, 5, 4, 3, 2, 1]).unwrap_err();
+        assert_eq!(Cycle(vec![4, 1, 2]), cycle);
+        assert_eq!("4|1, 1|2, 2|4", cycle.to_string());
+
+        // Leaving a page out of the update breaks the cycle
+        assert_eq!(Ok(vec![6, 5, 1, 2, 3]), rules.order(&[6, 5, 3, 2, 1]));
+
+        // Pages that point at each other
+        let rules = RuleSet::new(&[(7, 8), (8, 7)]);
+        assert_eq!(Err(Cycle(vec![8, 7])), rules.order(&[8, 7, 9]));
+
+        let mut input = parse(EXAMPLE);
+        input.rules.push((13, 97));
+        assert_eq!(Err(Cycle(vec![97, 13])), part2(input));
+    }
 }
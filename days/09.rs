@@ -1,4 +1,7 @@
-use std::fmt::Display;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+};
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 struct FreeBlock {
@@ -65,13 +68,448 @@ impl PartialOrd for Block {
 struct AllocMap {
     block_list: Vec<Block>,
     free_list: Vec<FreeBlock>,
+    size: usize,
+}
+
+impl AllocMap {
+    fn checksum(&self) -> usize {
+        self.block_list.iter().fold(0, |s, a| s + a.checksum())
+    }
+
+    // The free space between and after the blocks, merged into runs
+    fn free_runs(&self) -> Vec<FreeBlock> {
+        let mut blocks = self.block_list.clone();
+        blocks.retain(|b| b.len > 0);
+        blocks.sort();
+
+        let mut runs = Vec::new();
+        let mut offset = 0;
+        for b in blocks.iter() {
+            if b.offset > offset {
+                runs.push(FreeBlock {
+                    offset,
+                    len: (b.offset - offset) as u32,
+                });
+            }
+            offset = offset.max(b.offset + b.len as usize);
+        }
+        if self.size > offset {
+            runs.push(FreeBlock {
+                offset,
+                len: (self.size - offset) as u32,
+            });
+        }
+
+        runs
+    }
+
+    // Free runs with some file still after them
+    fn holes(&self) -> usize {
+        let end = self
+            .block_list
+            .iter()
+            .filter(|b| b.len > 0)
+            .map(|b| b.offset + b.len as usize)
+            .max()
+            .unwrap_or_default();
+        self.free_runs().iter().filter(|f| f.offset < end).count()
+    }
+
+    // How much of the free space is missing from the largest free run, from 0 when it's all in one
+    // piece up to almost 1 when it's scattered in tiny gaps
+    fn fragmentation(&self) -> f64 {
+        let runs = self.free_runs();
+        let total = runs.iter().map(|f| f.len as usize).sum::<usize>();
+        let largest = runs
+            .iter()
+            .map(|f| f.len as usize)
+            .max()
+            .unwrap_or_default();
+        if total == 0 {
+            0.0
+        } else {
+            1.0 - largest as f64 / total as f64
+        }
+    }
+
+    // Puts the lists back in order once a compaction is done
+    fn tidy(&mut self) {
+        self.block_list.retain(|b| b.len > 0);
+        self.block_list.sort();
+        self.free_list = self.free_runs();
+    }
+}
+
+// The disk as in the puzzle, file ids for used blocks and dots for free ones. Ids past 9 go on as
+// letters, a for 10 up to z for 35, and then start again from 0.
+impl Display for AllocMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut cells = vec!['.'; self.size];
+        for block in self.block_list.iter() {
+            let ch = char::from_digit((block.fileid % 36) as u32, 36).unwrap();
+            cells[block.offset..block.offset + block.len as usize].fill(ch);
+        }
+
+        write!(f, "{}", cells.into_iter().collect::<String>())
+    }
+}
+
+trait Compaction {
+    fn name(&self) -> &'static str;
+
+    // Moves blocks towards the start of the disk, calling `on_move` with the map after every move, and
+    // returns how many blocks were moved
+    fn compact(&self, map: &mut AllocMap, on_move: &mut dyn FnMut(&AllocMap)) -> usize;
+}
+
+// Fills the leftmost gap with the end of the last file, splitting files as needed
+struct BlockByBlock;
+
+impl Compaction for BlockByBlock {
+    fn name(&self) -> &'static str {
+        "block by block"
+    }
+
+    fn compact(&self, map: &mut AllocMap, on_move: &mut dyn FnMut(&AllocMap)) -> usize {
+        map.block_list.sort();
+        map.free_list.sort();
+
+        // Moved pieces are pushed after the original blocks, and the space they leave after the
+        // original gaps, so the ones still to look at are `..last` and `free..gaps`
+        let mut last = map.block_list.len();
+        let mut free = 0;
+        let gaps = map.free_list.len();
+        let mut moved = 0;
+        while last > 0 && free < gaps {
+            let block = map.block_list[last - 1];
+            let gap = map.free_list[free];
+            if gap.len == 0 {
+                free += 1;
+                continue;
+            }
+            if block.len == 0 {
+                last -= 1;
+                continue;
+            }
+            if block.offset <= gap.offset {
+                break;
+            }
+
+            let len = gap.len.min(block.len);
+            map.block_list.push(Block {
+                fileid: block.fileid,
+                offset: gap.offset,
+                len,
+            });
+            map.block_list[last - 1].len -= len;
+            map.free_list[free].offset += len as usize;
+            map.free_list[free].len -= len;
+            map.free_list.push(FreeBlock {
+                offset: block.offset + (block.len - len) as usize,
+                len,
+            });
+            moved += len as usize;
+
+            on_move(map);
+        }
+
+        map.tidy();
+        moved
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fit {
+    // The leftmost gap that fits
+    First,
+    // The smallest gap that fits
+    Best,
+    // The largest gap
+    Worst,
+}
+
+// Moves whole files once each, from the highest file id down, into a gap on their left if one fits
+struct WholeFile(Fit);
+
+impl Compaction for WholeFile {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            Fit::First => "whole file, first fit",
+            Fit::Best => "whole file, best fit",
+            Fit::Worst => "whole file, worst fit",
+        }
+    }
+
+    fn compact(&self, map: &mut AllocMap, on_move: &mut dyn FnMut(&AllocMap)) -> usize {
+        let mut index: Box<dyn FreeIndex> = match self.0 {
+            Fit::First => Box::new(FreeSpace::new(map.size, &map.free_list)),
+            fit => Box::new(Gaps::new(&map.free_list, fit)),
+        };
+
+        let mut files = (0..map.block_list.len()).collect::<Vec<_>>();
+        files.sort_by_key(|i| std::cmp::Reverse(map.block_list[*i].fileid));
+
+        let mut moved = 0;
+        for i in files {
+            let block = map.block_list[i];
+            let len = block.len as usize;
+            let Some(offset) = index.find(len, block.offset) else {
+                continue;
+            };
+
+            index.take(offset, len);
+            index.release(block.offset, len);
+            map.block_list[i].offset = offset;
+            moved += len;
+
+            on_move(map);
+        }
+
+        map.tidy();
+        moved
+    }
+}
+
+trait FreeIndex {
+    // Where to put `len` blocks, somewhere before `before`
+    fn find(&mut self, len: usize, before: usize) -> Option<usize>;
+    fn take(&mut self, offset: usize, len: usize);
+    fn release(&mut self, offset: usize, len: usize);
+}
+
+impl FreeIndex for FreeSpace {
+    fn find(&mut self, len: usize, before: usize) -> Option<usize> {
+        self.leftmost(len).filter(|o| *o < before)
+    }
+
+    fn take(&mut self, offset: usize, len: usize) {
+        self.set(offset, len, false);
+    }
+
+    fn release(&mut self, offset: usize, len: usize) {
+        self.set(offset, len, true);
+    }
+}
+
+// Gaps by offset, to merge neighbours, and by size, to pick the best or worst fit
+struct Gaps {
+    fit: Fit,
+    by_offset: BTreeMap<usize, usize>,
+    by_len: BTreeSet<(usize, usize)>,
+}
+
+impl Gaps {
+    fn new(free_list: &[FreeBlock], fit: Fit) -> Self {
+        let mut gaps = Self {
+            fit,
+            by_offset: BTreeMap::new(),
+            by_len: BTreeSet::new(),
+        };
+        for free in free_list.iter().filter(|f| f.len > 0) {
+            gaps.release(free.offset, free.len as usize);
+        }
+
+        gaps
+    }
+
+    fn insert(&mut self, offset: usize, len: usize) {
+        self.by_offset.insert(offset, len);
+        self.by_len.insert((len, offset));
+    }
+
+    fn remove(&mut self, offset: usize) -> usize {
+        let len = self.by_offset.remove(&offset).unwrap();
+        self.by_len.remove(&(len, offset));
+        len
+    }
+}
+
+impl FreeIndex for Gaps {
+    fn find(&mut self, len: usize, before: usize) -> Option<usize> {
+        match self.fit {
+            Fit::First => self
+                .by_offset
+                .iter()
+                .find(|(o, l)| **l >= len && **o < before)
+                .map(|(o, _)| *o),
+            Fit::Best => self
+                .by_len
+                .range((len, 0)..)
+                .find(|(_, o)| *o < before)
+                .map(|(_, o)| *o),
+            Fit::Worst => self
+                .by_len
+                .range((len, 0)..)
+                .rev()
+                .find(|(_, o)| *o < before)
+                .map(|(_, o)| *o),
+        }
+    }
+
+    fn take(&mut self, offset: usize, len: usize) {
+        let gap = self.remove(offset);
+        if gap > len {
+            self.insert(offset + len, gap - len);
+        }
+    }
+
+    fn release(&mut self, offset: usize, len: usize) {
+        let (mut start, mut end) = (offset, offset + len);
+        if let Some((o, l)) = self.by_offset.range(..offset).next_back() {
+            if o + l == offset {
+                start = *o;
+                self.remove(start);
+            }
+        }
+        if self.by_offset.contains_key(&end) {
+            end += self.remove(end);
+        }
+
+        self.insert(start, end - start);
+    }
+}
+
+// Free space on the disk, one leaf per disk block. Each node knows the longest run of free blocks
+// under it, and the free runs touching its two ends, which is enough to find the leftmost gap of a
+// given size in O(log n). Freeing a range merges it with whatever free space is next to it.
+#[derive(Debug, Clone)]
+struct FreeSpace {
+    size: usize,
+    nodes: Vec<Span>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Span {
+    len: usize,
+    longest: usize,
+    prefix: usize,
+    suffix: usize,
+    // Set when the whole span was just marked free or used, and its children haven't been told yet
+    pending: Option<bool>,
+}
+
+impl Span {
+    fn fill(&mut self, free: bool) {
+        let run = if free { self.len } else { 0 };
+        self.longest = run;
+        self.prefix = run;
+        self.suffix = run;
+        self.pending = Some(free);
+    }
+
+    fn join(left: Span, right: Span) -> Span {
+        Span {
+            len: left.len + right.len,
+            longest: left
+                .longest
+                .max(right.longest)
+                .max(left.suffix + right.prefix),
+            prefix: if left.prefix == left.len {
+                left.len + right.prefix
+            } else {
+                left.prefix
+            },
+            suffix: if right.suffix == right.len {
+                right.len + left.suffix
+            } else {
+                right.suffix
+            },
+            pending: None,
+        }
+    }
+}
+
+impl FreeSpace {
+    fn new(size: usize, free_list: &[FreeBlock]) -> Self {
+        let mut space = Self {
+            size,
+            nodes: vec![Span::default(); 4 * size.max(1)],
+        };
+        space.build(1, 0, size);
+        for free in free_list {
+            space.set(free.offset, free.len as usize, true);
+        }
+
+        space
+    }
+
+    fn build(&mut self, node: usize, start: usize, end: usize) {
+        self.nodes[node].len = end - start;
+        if end - start > 1 {
+            let mid = (start + end) / 2;
+            self.build(2 * node, start, mid);
+            self.build(2 * node + 1, mid, end);
+        }
+    }
+
+    fn push_down(&mut self, node: usize) {
+        if let Some(free) = self.nodes[node].pending.take() {
+            self.nodes[2 * node].fill(free);
+            self.nodes[2 * node + 1].fill(free);
+        }
+    }
+
+    // Marks blocks `offset..offset + len` as free or used
+    fn set(&mut self, offset: usize, len: usize, free: bool) {
+        self.set_in(1, 0, self.size, offset, offset + len, free);
+    }
+
+    fn set_in(
+        &mut self,
+        node: usize,
+        start: usize,
+        end: usize,
+        from: usize,
+        to: usize,
+        free: bool,
+    ) {
+        if to <= start || end <= from {
+            return;
+        }
+        if from <= start && end <= to {
+            self.nodes[node].fill(free);
+            return;
+        }
+
+        self.push_down(node);
+        let mid = (start + end) / 2;
+        self.set_in(2 * node, start, mid, from, to, free);
+        self.set_in(2 * node + 1, mid, end, from, to, free);
+        self.nodes[node] = Span::join(self.nodes[2 * node], self.nodes[2 * node + 1]);
+    }
+
+    // The offset of the leftmost run of at least `len` free blocks
+    fn leftmost(&mut self, len: usize) -> Option<usize> {
+        if self.size == 0 || self.nodes[1].longest < len {
+            return None;
+        }
+
+        let (mut node, mut start, mut end) = (1, 0, self.size);
+        while end - start > 1 {
+            self.push_down(node);
+            let mid = (start + end) / 2;
+            let (left, right) = (self.nodes[2 * node], self.nodes[2 * node + 1]);
+            if left.longest >= len {
+                (node, end) = (2 * node, mid);
+            } else if left.suffix + right.prefix >= len {
+                return Some(mid - left.suffix);
+            } else {
+                (node, start) = (2 * node + 1, mid);
+            }
+        }
+
+        Some(start)
+    }
 }
 
 fn parse_input() -> AllocMap {
-    let input = include_str!("../inputs/09.txt");
+    parse(include_str!("../inputs/09.txt"))
+}
+
+fn parse(input: &str) -> AllocMap {
     let mut map = AllocMap::default();
     let mut offset = 0;
-    for (i, ch) in input.chars().enumerate() {
+    for (i, ch) in input.trim().chars().enumerate() {
         if ch == '0' {
             continue;
         }
@@ -91,99 +529,182 @@ fn parse_input() -> AllocMap {
 
         offset += ch.to_digit(10).unwrap() as usize;
     }
+    map.size = offset;
 
     map
 }
 
 fn part1(mut allocs: AllocMap) -> usize {
-    let mut new_blocks = Vec::new();
+    BlockByBlock.compact(&mut allocs, &mut |_| {});
 
-    // Keep it reverse-sorted as we can then just pop them off
-    allocs.free_list.reverse();
+    allocs.checksum()
+}
 
-    while let Some(free) = allocs.free_list.pop() {
-        let Some(block_to_realloc) = allocs.block_list.pop() else {
-            break;
-        };
+fn part2(mut allocs: AllocMap) -> usize {
+    WholeFile(Fit::First).compact(&mut allocs, &mut |_| {});
 
-        if block_to_realloc.offset <= free.offset {
-            allocs.block_list.push(block_to_realloc);
-            break;
-        }
+    allocs.checksum()
+}
 
-        match free.len.cmp(&block_to_realloc.len) {
-            std::cmp::Ordering::Equal => {
-                new_blocks.push(Block {
-                    fileid: block_to_realloc.fileid,
-                    len: free.len,
-                    offset: free.offset,
-                });
-            }
-            std::cmp::Ordering::Less => {
-                new_blocks.push(Block {
-                    fileid: block_to_realloc.fileid,
-                    offset: free.offset,
-                    len: free.len,
-                });
-                allocs.block_list.push(Block {
-                    fileid: block_to_realloc.fileid,
-                    offset: block_to_realloc.offset,
-                    len: block_to_realloc.len - free.len,
-                });
-            }
-            std::cmp::Ordering::Greater => {
-                new_blocks.push(Block {
-                    fileid: block_to_realloc.fileid,
-                    offset: free.offset,
-                    len: block_to_realloc.len,
-                });
-                allocs.free_list.push(FreeBlock {
-                    offset: free.offset + block_to_realloc.len as usize,
-                    len: free.len - block_to_realloc.len,
-                })
-            }
-        }
+fn compare(allocs: &AllocMap) {
+    let strategies: [&dyn Compaction; 4] = [
+        &BlockByBlock,
+        &WholeFile(Fit::First),
+        &WholeFile(Fit::Best),
+        &WholeFile(Fit::Worst),
+    ];
+    for strategy in strategies {
+        let mut map = allocs.clone();
+        let moved = strategy.compact(&mut map, &mut |_| {});
+        println!(
+            "{:>22}: checksum {}, moved {moved} blocks, {} holes, {:.3} fragmentation",
+            strategy.name(),
+            map.checksum(),
+            map.holes(),
+            map.fragmentation()
+        );
     }
+}
 
-    allocs.block_list.append(&mut new_blocks);
-    allocs.block_list.sort(); // For debugging purposes
+pub fn main() {
+    let input = parse_input();
 
-    allocs.block_list.iter().fold(0, |s, a| s + a.checksum())
+    // `cargo run --bin 09 -- compare` runs every compaction strategy on the disk and sums up how
+    // each one leaves it
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("compare") {
+        compare(&input);
+        return;
+    }
+
+    println!("Part 1: {}", part1(input.clone()));
+    println!("Part 2: {}", part2(input.clone()));
 }
 
-fn part2(mut input: AllocMap) -> usize {
-    for block in input.block_list.iter_mut().rev() {
-        if input.free_list.is_empty() {
-            break;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let Some(free_index) = input
-            .free_list
-            .iter()
-            .position(|free| free.len >= block.len && free.offset < block.offset)
-        else {
-            continue;
-        };
+    const EXAMPLE: &str = "2333133121414131402";
+
+    #[test]
+    fn test_example() {
+        assert_eq!(1928, part1(parse(EXAMPLE)));
+        assert_eq!(2858, part2(parse(EXAMPLE)));
+    }
+
+    #[test]
+    fn test_free_space() {
+        // ..##...#.
+        let mut space = FreeSpace::new(
+            9,
+            &[
+                FreeBlock { offset: 0, len: 2 },
+                FreeBlock { offset: 4, len: 3 },
+                FreeBlock { offset: 8, len: 1 },
+            ],
+        );
+        assert_eq!(Some(0), space.leftmost(1));
+        assert_eq!(Some(0), space.leftmost(2));
+        assert_eq!(Some(4), space.leftmost(3));
+        assert_eq!(None, space.leftmost(4));
 
-        let mut free = input.free_list.remove(free_index);
+        // Freeing the blocks in between merges the gaps: .......#.
+        space.set(2, 2, true);
+        assert_eq!(Some(0), space.leftmost(7));
+        assert_eq!(None, space.leftmost(8));
 
-        block.offset = free.offset;
+        // ###....#.
+        space.set(0, 3, false);
+        assert_eq!(Some(3), space.leftmost(2));
+        assert_eq!(Some(3), space.leftmost(4));
 
-        if free.len > block.len {
-            free.offset += block.len as usize;
-            free.len -= block.len;
-            input.free_list.push(free);
-            input.free_list.sort();
+        // #########
+        space.set(3, 4, false);
+        space.set(8, 1, false);
+        assert_eq!(None, space.leftmost(1));
+    }
+
+    #[test]
+    fn test_block_by_block_steps() {
+        let expected = [
+            "00...111...2...333.44.5555.6666.777.888899",
+            "009..111...2...333.44.5555.6666.777.88889.",
+            "0099.111...2...333.44.5555.6666.777.8888..",
+            "00998111...2...333.44.5555.6666.777.888...",
+            "009981118..2...333.44.5555.6666.777.88....",
+            "0099811188.2...333.44.5555.6666.777.8.....",
+            "009981118882...333.44.5555.6666.777.......",
+            "0099811188827..333.44.5555.6666.77........",
+            "00998111888277.333.44.5555.6666.7.........",
+            "009981118882777333.44.5555.6666...........",
+            "009981118882777333644.5555.666............",
+            "00998111888277733364465555.66.............",
+            "0099811188827773336446555566..............",
+        ];
+
+        let mut map = parse(EXAMPLE);
+        assert_eq!(expected[0], map.to_string());
+
+        // Moves are a whole gap or a whole file at a time, so they skip some of the puzzle's steps
+        let mut steps = Vec::new();
+        let moved = BlockByBlock.compact(&mut map, &mut |m| steps.push(m.to_string()));
+        for step in steps.iter() {
+            assert!(expected.contains(&step.as_str()), "{step}");
         }
+        assert_eq!(expected[12], map.to_string());
+        assert_eq!(12, moved);
+        assert_eq!(0, map.holes());
+        assert_eq!(0.0, map.fragmentation());
+    }
 
-        // TODO: CHECK IF WE HAVE contiguous spots
+    #[test]
+    fn test_whole_file_steps() {
+        let mut map = parse(EXAMPLE);
+        let mut steps = Vec::new();
+        let moved = WholeFile(Fit::First).compact(&mut map, &mut |m| steps.push(m.to_string()));
+
+        assert_eq!(
+            vec![
+                "0099.111...2...333.44.5555.6666.777.8888..",
+                "0099.1117772...333.44.5555.6666.....8888..",
+                "0099.111777244.333....5555.6666.....8888..",
+                "00992111777.44.333....5555.6666.....8888..",
+            ],
+            steps
+        );
+        assert_eq!(2858, map.checksum());
+        assert_eq!(2 + 3 + 2 + 1, moved);
+        assert_eq!(5, map.holes());
+        assert_eq!(1.0 - 5.0 / 14.0, map.fragmentation());
     }
 
-    input.block_list.iter().fold(0, |s, a| s + a.checksum())
-}
+    #[test]
+    fn test_fits() {
+        // The 2s fit in either gap, and then the 1 moves too
+        let input = "13122";
+        assert_eq!("0...1..22", parse(input).to_string());
 
-pub fn main() {
-    let input = parse_input();
-    println!("Part 1: {}", part1(input.clone()));
-    println!("Part 2: {}", part2(input.clone()));
+        for (fit, expected) in [
+            (Fit::First, "0221....."),
+            (Fit::Best, "01...22.."),
+            (Fit::Worst, "0221....."),
+        ] {
+            let mut map = parse(input);
+            WholeFile(fit).compact(&mut map, &mut |_| {});
+            assert_eq!(expected, map.to_string());
+        }
+    }
+
+    #[test]
+    fn test_many_files() {
+        // 40 one block files, with no gaps and then with a gap after each
+        let map = parse(&"10".repeat(40));
+        assert_eq!("0123456789abcdefghijklmnopqrstuvwxyz0123", map.to_string());
+
+        let mut map = parse(&"11".repeat(12));
+        assert_eq!("0.1.2.3.4.5.6.7.8.9.a.b.", map.to_string());
+        BlockByBlock.compact(&mut map, &mut |_| {});
+        assert_eq!("0b1a29384756............", map.to_string());
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{HashMap, HashSet};
 
 use grid::Grid;
 
@@ -53,7 +53,7 @@ fn part1(input: Grid<u8>) -> usize {
 
 fn reach_nines(
     start: (usize, usize),
-    exit_nodes: &HashSet<(usize, usize)>,
+    _exit_nodes: &HashSet<(usize, usize)>,
     edges: &HashMap<(usize, usize), Vec<(usize, usize)>>,
     labels: &HashMap<(usize, usize), u8>,
 ) -> usize {
@@ -120,7 +120,7 @@ fn part2(input: Grid<u8>) -> usize {
 
 fn rating_nines(
     start: (usize, usize),
-    exit_nodes: &HashSet<(usize, usize)>,
+    _exit_nodes: &HashSet<(usize, usize)>,
     edges: &HashMap<(usize, usize), Vec<(usize, usize)>>,
     labels: &HashMap<(usize, usize), u8>,
 ) -> usize {
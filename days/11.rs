@@ -1,57 +1,329 @@
-use std::collections::HashMap;
-
-fn iter(freqs: HashMap<usize, usize>) -> HashMap<usize, usize> {
-    let mut new = HashMap::with_capacity(freqs.capacity());
-
-    for (num, count) in freqs.into_iter() {
-        let digits = count_digits(num);
-        match num {
-            0 => inc(&mut new, 1, count),
-            x if digits % 2 == 0 => {
-                let div = 10usize.pow(digits as u32 / 2);
-                inc(&mut new, x / div, count);
-                inc(&mut new, x % div, count)
+use std::{collections::HashMap, fmt::Display, ops::AddAssign};
+
+type Stone = u128;
+
+// Anything the stones can be counted with. usize is enough for the puzzle, u128 lasts a couple hundred
+// blinks, and BigCount doesn't run out.
+trait Count: Clone + Default + From<u8> + for<'a> AddAssign<&'a Self> {}
+
+impl<C> Count for C where C: Clone + Default + From<u8> + for<'a> AddAssign<&'a Self> {}
+
+// Just enough of an unsigned big integer to add up stone counts, as little-endian 64 bit limbs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct BigCount(Vec<u64>);
+
+impl From<u8> for BigCount {
+    fn from(n: u8) -> Self {
+        Self::from(n as u128)
+    }
+}
+
+impl From<u128> for BigCount {
+    fn from(n: u128) -> Self {
+        let mut limbs = vec![n as u64, (n >> 64) as u64];
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        Self(limbs)
+    }
+}
+
+impl AddAssign<&BigCount> for BigCount {
+    fn add_assign(&mut self, other: &BigCount) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+
+        let mut carry = false;
+        for (i, limb) in self.0.iter_mut().enumerate() {
+            let (sum, c1) = limb.overflowing_add(other.0.get(i).copied().unwrap_or_default());
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            *limb = sum;
+            carry = c1 || c2;
+        }
+        if carry {
+            self.0.push(1);
+        }
+    }
+}
+
+impl Display for BigCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Peel off 19 decimal digits at a time, the most that fit in a limb
+        const CHUNK: u128 = 10_000_000_000_000_000_000;
+        let mut limbs = self.0.clone();
+        let mut chunks = Vec::new();
+        while !limbs.is_empty() {
+            let mut rem = 0u128;
+            for limb in limbs.iter_mut().rev() {
+                let cur = (rem << 64) | *limb as u128;
+                *limb = (cur / CHUNK) as u64;
+                rem = cur % CHUNK;
             }
-            _ => inc(&mut new, num * 2024, count),
-        };
+            chunks.push(rem as u64);
+            while limbs.last() == Some(&0) {
+                limbs.pop();
+            }
+        }
+
+        match chunks.split_last() {
+            None => write!(f, "0"),
+            Some((first, rest)) => {
+                write!(f, "{first}")?;
+                for chunk in rest.iter().rev() {
+                    write!(f, "{chunk:019}")?;
+                }
+                Ok(())
+            }
+        }
     }
+}
+
+// One rule per line, `<predicate> -> <transform>`, and the first rule whose predicate matches a stone
+// decides what it turns into. A stone no rule matches stays as it is.
+// Predicates: a number, `even-digits`, `odd-digits`, `divisible-by <n>`, or `*` for anything.
+// Transforms: one or more numbers, `split` to cut the digits in half, `mul <n>` or `add <n>`.
+const STANDARD_RULES: &str = "
+0 -> 1
+even-digits -> split
+* -> mul 2024
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    Is(Stone),
+    EvenDigits,
+    OddDigits,
+    DivisibleBy(Stone),
+    Any,
+}
 
-    new
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Transform {
+    Becomes(Vec<Stone>),
+    Split,
+    Mul(Stone),
+    Add(Stone),
 }
-#[inline]
-fn inc(map: &mut HashMap<usize, usize>, k: usize, count: usize) {
-    let entry = map.entry(k).or_default();
-    *entry += count;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rules(Vec<(Predicate, Transform)>);
+
+impl Rules {
+    fn standard() -> Self {
+        Self::parse(STANDARD_RULES).unwrap()
+    }
+
+    fn parse(spec: &str) -> Result<Self, String> {
+        let number = |n: &str| {
+            n.parse::<Stone>()
+                .map_err(|_| format!("`{n}` is not a stone number"))
+        };
+
+        let mut rules = Vec::new();
+        for line in spec.lines() {
+            let line = line.split_once('#').map_or(line, |(l, _)| l).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((predicate, transform)) = line.split_once("->") else {
+                return Err(format!("expected `<predicate> -> <transform>` in `{line}`"));
+            };
+
+            let predicate = match predicate.split_whitespace().collect::<Vec<_>>()[..] {
+                ["*"] => Predicate::Any,
+                ["even-digits"] => Predicate::EvenDigits,
+                ["odd-digits"] => Predicate::OddDigits,
+                ["divisible-by", n] => Predicate::DivisibleBy(number(n)?),
+                [n] => Predicate::Is(number(n)?),
+                _ => return Err(format!("unknown predicate in `{line}`")),
+            };
+
+            let transform = match transform.split_whitespace().collect::<Vec<_>>()[..] {
+                ["split"] => Transform::Split,
+                ["mul", n] => Transform::Mul(number(n)?),
+                ["add", n] => Transform::Add(number(n)?),
+                [] => return Err(format!("missing transform in `{line}`")),
+                ref ns => {
+                    Transform::Becomes(ns.iter().map(|n| number(n)).collect::<Result<_, _>>()?)
+                }
+            };
+
+            rules.push((predicate, transform));
+        }
+
+        Ok(Self(rules))
+    }
+
+    fn apply(&self, stone: Stone) -> Vec<Stone> {
+        let digits = count_digits(stone);
+        let rule = self.0.iter().find(|(predicate, _)| match predicate {
+            Predicate::Is(n) => stone == *n,
+            Predicate::EvenDigits => digits.is_multiple_of(2),
+            Predicate::OddDigits => !digits.is_multiple_of(2),
+            Predicate::DivisibleBy(n) => stone.is_multiple_of(*n),
+            Predicate::Any => true,
+        });
+
+        let Some((_, transform)) = rule else {
+            return vec![stone];
+        };
+        match transform {
+            Transform::Becomes(stones) => stones.clone(),
+            Transform::Split => {
+                let div = (10 as Stone).pow(digits as u32 / 2);
+                vec![stone / div, stone % div]
+            }
+            Transform::Mul(n) => vec![stone
+                .checked_mul(*n)
+                .unwrap_or_else(|| panic!("{stone} * {n} doesn't fit in a stone"))],
+            Transform::Add(n) => vec![stone
+                .checked_add(*n)
+                .unwrap_or_else(|| panic!("{stone} + {n} doesn't fit in a stone"))],
+        }
+    }
 }
 
 #[inline(always)]
-fn count_digits(n: usize) -> usize {
-    1 + n.abs_diff(0).checked_ilog10().unwrap_or_default() as usize
+fn count_digits(n: Stone) -> usize {
+    1 + n.checked_ilog10().unwrap_or_default() as usize
 }
 
-fn count(freqs: HashMap<usize, usize>) -> usize {
-    freqs.values().sum()
+fn count<C: Count>(freqs: &HashMap<Stone, C>) -> C {
+    freqs.values().fold(C::default(), |mut sum, c| {
+        sum += c;
+        sum
+    })
 }
 
-fn part1(input: Vec<usize>) -> usize {
-    let mut freqs: HashMap<usize, usize> = input.into_iter().map(|n| (n, 1)).collect();
-    for _i in 0..25 {
-        freqs = iter(freqs);
+// Counts how many stones of each value there are after 0, 1, 2... blinks, for as long as you keep
+// asking. Every value met gets an index, and what it turns into is only worked out once. As soon as a
+// blink brings no new value, every value's successors are known, so no later blink can bring one
+// either and each blink is a fixed walk over the same transitions.
+struct Blinks<'a, C> {
+    rules: &'a Rules,
+    values: Vec<Stone>,
+    index: HashMap<Stone, usize>,
+    successors: Vec<Option<Vec<usize>>>,
+    counts: Vec<C>,
+    present: Vec<bool>,
+    blink: usize,
+    saturated: Option<usize>,
+}
+
+impl<C: Count> Blinks<'_, C> {
+    fn intern(&mut self, stone: Stone) -> usize {
+        if let Some(i) = self.index.get(&stone) {
+            return *i;
+        }
+
+        self.values.push(stone);
+        self.successors.push(None);
+        self.counts.push(C::default());
+        self.present.push(false);
+        self.index.insert(stone, self.values.len() - 1);
+        self.values.len() - 1
+    }
+
+    fn successors(&mut self, i: usize) -> Vec<usize> {
+        if let Some(ref next) = self.successors[i] {
+            return next.clone();
+        }
+
+        let next = self
+            .rules
+            .apply(self.values[i])
+            .into_iter()
+            .map(|stone| self.intern(stone))
+            .collect::<Vec<_>>();
+        self.successors[i] = Some(next.clone());
+        next
     }
 
-    count(freqs)
+    // The last blink that brought new values, once we know no later one will
+    fn saturated(&self) -> Option<usize> {
+        self.saturated
+    }
+}
+
+impl<C: Count> Iterator for Blinks<'_, C> {
+    type Item = HashMap<Stone, C>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let freqs = (0..self.values.len())
+            .filter(|i| self.present[*i])
+            .map(|i| (self.values[i], self.counts[i].clone()))
+            .collect();
+
+        let known = self.values.len();
+        let mut counts = vec![C::default(); known];
+        let mut present = vec![false; known];
+        for i in 0..known {
+            if !self.present[i] {
+                continue;
+            }
+            for j in self.successors(i) {
+                if j >= counts.len() {
+                    counts.resize(j + 1, C::default());
+                    present.resize(j + 1, false);
+                }
+                counts[j] += &self.counts[i];
+                present[j] = true;
+            }
+        }
+        counts.resize(self.values.len(), C::default());
+        present.resize(self.values.len(), false);
+        self.counts = counts;
+        self.present = present;
+
+        if self.saturated.is_none() && self.values.len() == known {
+            self.saturated = Some(self.blink);
+        }
+        self.blink += 1;
+
+        Some(freqs)
+    }
 }
 
-fn part2(input: Vec<usize>) -> usize {
-    let mut freqs: HashMap<usize, usize> = input.into_iter().map(|n| (n, 1)).collect();
-    for _i in 0..75 {
-        freqs = iter(freqs);
+fn blinks<'a, C: Count>(rules: &'a Rules, input: &[Stone]) -> Blinks<'a, C> {
+    let mut blinks = Blinks {
+        rules,
+        values: Vec::new(),
+        index: HashMap::new(),
+        successors: Vec::new(),
+        counts: Vec::new(),
+        present: Vec::new(),
+        blink: 0,
+        saturated: None,
+    };
+    for n in input {
+        let i = blinks.intern(*n);
+        blinks.counts[i] += &C::from(1);
+        blinks.present[i] = true;
     }
 
-    count(freqs)
+    blinks
 }
 
-fn parse_input() -> Vec<usize> {
+// The stones one by one, in order, to check the frequency counts against
+#[cfg(test)]
+fn simulate(rules: &Rules, mut stones: Vec<Stone>, blinks: usize) -> Vec<Stone> {
+    for _i in 0..blinks {
+        stones = stones.into_iter().flat_map(|n| rules.apply(n)).collect();
+    }
+
+    stones
+}
+
+fn part1(input: &[Stone]) -> usize {
+    count(&blinks::<usize>(&Rules::standard(), input).nth(25).unwrap())
+}
+
+fn part2(input: &[Stone]) -> usize {
+    count(&blinks::<usize>(&Rules::standard(), input).nth(75).unwrap())
+}
+
+fn parse_input() -> Vec<Stone> {
     let input = include_str!("../inputs/11.txt");
     input
         .split_ascii_whitespace()
@@ -61,6 +333,140 @@ fn parse_input() -> Vec<usize> {
 
 pub fn main() {
     let input = parse_input();
-    println!("Part 1: {}", part1(input.clone()));
-    println!("Part 2: {}", part2(input));
+
+    // `cargo run --bin 11 -- trace <blinks> [rules file]` shows how the stones grow instead, blink by
+    // blink, with the standard rules or the ones in the file
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("trace") {
+        let n = args
+            .next()
+            .map(|n| n.parse::<usize>().expect("number of blinks"))
+            .unwrap_or(75);
+        let rules = match args.next() {
+            Some(path) => Rules::parse(&std::fs::read_to_string(&path).unwrap())
+                .unwrap_or_else(|e| panic!("{path}: {e}")),
+            None => Rules::standard(),
+        };
+
+        let mut stones = blinks::<BigCount>(&rules, &input);
+        for (blink, freqs) in stones.by_ref().take(n + 1).enumerate() {
+            let mut by_value = freqs.iter().collect::<Vec<_>>();
+            by_value.sort_by_key(|(stone, _)| **stone);
+            let values = by_value
+                .iter()
+                .take(5)
+                .map(|(stone, c)| format!("{stone}x{c}"))
+                .collect::<Vec<_>>();
+            println!(
+                "{blink:>4}: {} stones, {} distinct values ({}{})",
+                count(&freqs),
+                freqs.len(),
+                values.join(" "),
+                if freqs.len() > 5 { " ..." } else { "" }
+            );
+        }
+        if let Some(blink) = stones.saturated() {
+            println!("No new values after blink {blink}");
+        }
+        return;
+    }
+
+    println!("Part 1: {}", part1(&input));
+    println!("Part 2: {}", part2(&input));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example() {
+        assert_eq!(55312, part1(&[125, 17]));
+    }
+
+    #[test]
+    fn test_against_simulation() {
+        let input = [0, 1, 10, 99, 999, 2024, 125, 17];
+        let rules = Rules::standard();
+        for (blink, freqs) in blinks::<u128>(&rules, &input).take(20).enumerate() {
+            let stones = simulate(&rules, input.to_vec(), blink);
+            assert_eq!(stones.len() as u128, count(&freqs), "blink {blink}");
+
+            let mut expected: HashMap<Stone, u128> = HashMap::new();
+            for s in stones {
+                *expected.entry(s).or_default() += 1;
+            }
+            assert_eq!(expected, freqs, "blink {blink}");
+        }
+    }
+
+    #[test]
+    fn test_big_count() {
+        let mut n = BigCount::from(u128::MAX);
+        assert_eq!(u128::MAX.to_string(), n.to_string());
+
+        n += &BigCount::from(1u8);
+        assert_eq!("340282366920938463463374607431768211456", n.to_string());
+        assert_eq!("0", BigCount::default().to_string());
+
+        let rules = Rules::standard();
+
+        assert_eq!(
+            count(&blinks::<u128>(&rules, &[125, 17]).nth(150).unwrap()).to_string(),
+            count(&blinks::<BigCount>(&rules, &[125, 17]).nth(150).unwrap()).to_string()
+        );
+
+        // Well past where u128 overflows
+        let freqs = blinks::<BigCount>(&rules, &[125, 17]).nth(300).unwrap();
+        assert!(count(&freqs).0.len() > 2);
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_eq!(
+            Rules(vec![
+                (Predicate::Is(0), Transform::Becomes(vec![1])),
+                (Predicate::EvenDigits, Transform::Split),
+                (Predicate::Any, Transform::Mul(2024)),
+            ]),
+            Rules::standard()
+        );
+
+        let rules = Rules::parse(
+            "
+            # odd stones fall apart
+            divisible-by 2 -> add 1
+            odd-digits -> 3 4 # three digits or one
+            ",
+        )
+        .unwrap();
+        assert_eq!(vec![11], rules.apply(10));
+        assert_eq!(vec![3, 4], rules.apply(7));
+        assert_eq!(vec![13], rules.apply(13));
+
+        assert!(Rules::parse("0 -> 1\nprime -> 2").is_err());
+        assert!(Rules::parse("0 ->").is_err());
+        assert!(Rules::parse("0 -> mul").is_err());
+    }
+
+    #[test]
+    fn test_saturation() {
+        let rules = Rules::standard();
+        let mut stones = blinks::<BigCount>(&rules, &[125, 17]);
+        // The standard rules only ever reach a few thousand values
+        stones.by_ref().take(200).for_each(drop);
+        assert!(stones.saturated().is_some_and(|blink| blink < 200));
+        let known = stones.values.len();
+        assert!(known < 4000);
+
+        stones.by_ref().take(100).for_each(drop);
+        assert_eq!(known, stones.values.len());
+
+        // Halving forever can't go anywhere new once it reaches 0
+        let rules = Rules::parse("0 -> 0\neven-digits -> split\n* -> 0").unwrap();
+        let mut stones = blinks::<u128>(&rules, &[1234]);
+        let last = stones.by_ref().nth(5).unwrap();
+        assert_eq!(Some(3), stones.saturated());
+        assert_eq!(HashMap::from([(0, 4)]), last);
+    }
 }
@@ -87,6 +87,7 @@ impl Region {
     }
 
     // This crawls the walls, which gives the correct count for polygons without holes
+    #[allow(dead_code)]
     fn broken_sides(&self) -> usize {
         let mut turns = 0;
         let start_pos = *self.vertices.first().unwrap();
@@ -148,6 +149,7 @@ impl Side {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 enum Dir {
     North,
@@ -156,6 +158,7 @@ enum Dir {
     West,
 }
 
+#[allow(dead_code)]
 impl Dir {
     fn forward(&self, pos: (usize, usize)) -> Option<(usize, usize)> {
         match self {
@@ -10,56 +10,112 @@ struct Game {
 // n_a * a.x + n_b * b.x = p.x
 // n_a * a.y + n_b * b.y = p.y
 
-// n_a * a.x + n_b * b.x = p.x
-// n_a = (p.x - n_b * b.x) / a.x
+// By Cramer's rule, with det = a.x * b.y - a.y * b.x:
+// n_a = (p.x * b.y - p.y * b.x) / det
+// n_b = (a.x * p.y - a.y * p.x) / det
+// which has to divide exactly for the machine to be winnable.
+fn solve(game: Game) -> Option<(usize, usize)> {
+    let Game { a, b, prize: p } = game;
+    let (ax, ay, bx, by) = (a.x as i128, a.y as i128, b.x as i128, b.y as i128);
+    let (px, py) = (p.x as i128, p.y as i128);
+
+    let det = ax * by - ay * bx;
+    if det == 0 {
+        return solve_collinear(game);
+    }
 
-// ((p.x - n_b * b.x) / a.x) * a.y + n_b * b.y = p.y
-// (p.x - n_b * b.x) * a.y + n_b * b.y * a.x = p.y * a.x
-// p.x - n_b * b.x + n_b * b.y * a.x / a.y = p.y * a.x / a.y
-// p.x + n_b * b.y * a.x / a.y - n_b * b.x = p.y * a.x / a.y
-// n_b * b.y * a.x / a.y - n_b * b.x = p.y * a.x / a.y - p.x
-// n_b * (b.y * a.x / a.y - b.x) = p.y * a.x / a.y - p.x
-// n_b = (p.y * a.x / a.y - p.x) / (b.y * a.x / a.y - b.x)
+    let n_a = px * by - py * bx;
+    let n_b = ax * py - ay * px;
+    if n_a % det != 0 || n_b % det != 0 {
+        return None;
+    }
 
-// n_a = (p.x - n_b * b.x) / a.x
-// n_b = (p.y * a.x / a.y - p.x) / (b.y * a.x / a.y - b.x)
+    let (n_a, n_b) = (n_a / det, n_b / det);
+    if n_a < 0 || n_b < 0 {
+        return None;
+    }
 
-const EPSILON: f64 = 0.0001;
+    Some((n_a as usize, n_b as usize))
+}
 
-fn n_b(Game { a, b, prize: p }: Game) -> Option<usize> {
-    let p0 = p.y as f64 * a.x as f64 / a.y as f64 - p.x as f64;
-    let p1 = b.y as f64 * a.x as f64 / a.y as f64 - b.x as f64;
+// When both buttons move the claw along the same line, the prize has to be on that line too, and then
+// there's a whole family of solutions along one axis:
+// n_a * a_k + n_b * b_k = p_k
+// With g = gcd(a_k, b_k) and one solution (n_a0, n_b0) from the extended gcd, they are
+// n_a = n_a0 + t * b_k / g
+// n_b = n_b0 - t * a_k / g
+// and the cost is linear in t, so the cheapest non-negative one is at one end of the range of t.
+fn solve_collinear(Game { a, b, prize: p }: Game) -> Option<(usize, usize)> {
+    let (ax, ay, bx, by) = (a.x as i128, a.y as i128, b.x as i128, b.y as i128);
+    let (px, py) = (p.x as i128, p.y as i128);
+
+    let (dx, dy) = if (ax, ay) != (0, 0) {
+        (ax, ay)
+    } else {
+        (bx, by)
+    };
+    if (dx, dy) == (0, 0) {
+        return (p.x == 0 && p.y == 0).then_some((0, 0));
+    }
+    if px * dy != py * dx {
+        return None;
+    }
 
-    let n_b = p0 / p1;
-    let n_b_rounded = n_b.round();
+    let (a_k, b_k, p_k) = if dx != 0 { (ax, bx, px) } else { (ay, by, py) };
 
-    if (n_b - n_b_rounded).abs() < EPSILON {
-        Some(n_b_rounded as usize)
-    } else {
-        None
+    // A button that doesn't move the claw is never worth pressing
+    if a_k == 0 || b_k == 0 {
+        let (step, n_a) = if a_k == 0 { (b_k, false) } else { (a_k, true) };
+        if p_k % step != 0 {
+            return None;
+        }
+        let n = (p_k / step) as usize;
+        return Some(if n_a { (n, 0) } else { (0, n) });
     }
-}
 
-fn n_a(Game { a, b, prize: p }: Game, n_b: usize) -> Option<usize> {
-    // n_a = (p.x - n_b * b.x) / a.x
-    let a = (p.x as f64 - n_b as f64 * b.x as f64) / a.x as f64;
-    let a_rounded = a.round();
+    let (g, u, v) = ext_gcd(a_k, b_k);
+    if p_k % g != 0 {
+        return None;
+    }
+    let (n_a0, n_b0) = (u * (p_k / g), v * (p_k / g));
+    let (step_a, step_b) = (b_k / g, a_k / g);
+
+    // n_a >= 0 and n_b >= 0
+    let t_min = div_ceil(-n_a0, step_a);
+    let t_max = n_b0.div_euclid(step_b);
+    if t_min > t_max {
+        return None;
+    }
+
+    let slope = cost(step_a as usize, 0) as i128 - step_b;
+    let t = if slope > 0 { t_min } else { t_max };
+
+    Some(((n_a0 + t * step_a) as usize, (n_b0 - t * step_b) as usize))
+}
 
-    if (a - a_rounded).abs() < EPSILON {
-        Some(a_rounded as usize)
+// Returns (g, u, v) with a * u + b * v = g
+fn ext_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
     } else {
-        None
+        let (g, u, v) = ext_gcd(b, a % b);
+        (g, v, u - (a / b) * v)
     }
 }
 
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -(-a).div_euclid(b)
+}
+
 impl Game {
     fn cost(&self) -> Option<usize> {
-        let n_b = n_b(*self)?;
-
-        let n_a = n_a(*self, n_b)?;
+        let (n_a, n_b) = solve(*self)?;
 
         Some(cost(n_a, n_b))
     }
+
+    // Brute force over n_a, only there to check `solve` against
+    #[cfg(test)]
     fn min_cost(&self) -> Option<usize> {
         // find all values of (n_a, n_b) that satisfy the 2 equations:
         // n_a * a.x + n_b * b.x = p.x
@@ -74,8 +130,8 @@ impl Game {
             let rem_x = self.prize.x - n_a * self.a.x;
             let rem_y = self.prize.y - n_a * self.a.y;
 
-            if rem_x % self.b.x == 0
-                && rem_y % self.b.y == 0
+            if rem_x.is_multiple_of(self.b.x)
+                && rem_y.is_multiple_of(self.b.y)
                 && rem_x / self.b.x == rem_y / self.b.y
             {
                 let n_b = rem_x / self.b.x;
@@ -114,6 +170,125 @@ struct Prize {
     y: usize,
 }
 
+// A claw machine with any number of buttons, each with its own cost and optionally a limit on how many
+// times it can be pressed
+#[derive(Debug, Clone)]
+struct Machine {
+    controls: Vec<Control>,
+    prize: Prize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Control {
+    button: Button,
+    cost: usize,
+    limit: Option<usize>,
+}
+
+impl Machine {
+    fn from_game(game: &Game, limit: Option<usize>) -> Self {
+        Self {
+            controls: vec![
+                Control {
+                    button: game.a,
+                    cost: cost(1, 0),
+                    limit,
+                },
+                Control {
+                    button: game.b,
+                    cost: cost(0, 1),
+                    limit,
+                },
+            ],
+            prize: game.prize,
+        }
+    }
+
+    // The cheapest way to win as (cost, presses of each button), by branch and bound over the number of
+    // presses of each button in turn. This enumerates, so it's meant for small prizes or limited presses.
+    fn cheapest(&self) -> Option<(usize, Vec<usize>)> {
+        let mut best = None;
+        let mut presses = vec![0; self.controls.len()];
+        self.search(0, self.prize.x, self.prize.y, 0, &mut presses, &mut best);
+
+        best
+    }
+
+    fn search(
+        &self,
+        i: usize,
+        rem_x: usize,
+        rem_y: usize,
+        spent: usize,
+        presses: &mut [usize],
+        best: &mut Option<(usize, Vec<usize>)>,
+    ) {
+        if rem_x == 0 && rem_y == 0 {
+            if best.as_ref().is_none_or(|(c, _)| spent < *c) {
+                *best = Some((spent, presses.to_vec()));
+            }
+            return;
+        }
+
+        let Some(bound) = self.lower_bound(i, rem_x, rem_y) else {
+            return;
+        };
+        if best.as_ref().is_some_and(|(c, _)| spent + bound >= *c) {
+            return;
+        }
+
+        let Control {
+            button,
+            cost,
+            limit,
+        } = self.controls[i];
+        // Don't overshoot, and a button that doesn't move the claw is never worth pressing
+        let max = if button.x == 0 && button.y == 0 {
+            0
+        } else {
+            [
+                rem_x.checked_div(button.x),
+                rem_y.checked_div(button.y),
+                limit,
+            ]
+            .into_iter()
+            .flatten()
+            .min()
+            .unwrap()
+        };
+
+        for n in (0..=max).rev() {
+            presses[i] = n;
+            self.search(
+                i + 1,
+                rem_x - n * button.x,
+                rem_y - n * button.y,
+                spent + n * cost,
+                presses,
+                best,
+            );
+        }
+        presses[i] = 0;
+    }
+
+    // What's left to pay at least, going with the cheapest rate per step on each axis, or None if the
+    // buttons left can't move the claw far enough in some direction
+    fn lower_bound(&self, i: usize, rem_x: usize, rem_y: usize) -> Option<usize> {
+        let axis = |rem: usize, step: fn(&Button) -> usize| {
+            if rem == 0 {
+                return Some(0);
+            }
+            self.controls[i..]
+                .iter()
+                .filter(|c| step(&c.button) > 0)
+                .map(|c| (rem * c.cost).div_ceil(step(&c.button)))
+                .min()
+        };
+
+        Some(axis(rem_x, |b| b.x)?.max(axis(rem_y, |b| b.y)?))
+    }
+}
+
 fn parse_input() -> Vec<Game> {
     let input = include_str!("../inputs/13.txt");
     let button_regex = Regex::new(r#"Button .: X\+(\d+), Y\+(\d+)"#).unwrap();
@@ -162,6 +337,21 @@ fn part2(mut input: Vec<Game>) -> usize {
 
 pub fn main() {
     let input = parse_input();
+
+    // `cargo run --bin 13 -- limit <presses>` runs each machine through the general solver with no
+    // button pressed more than that many times, and shows the presses it takes
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("limit") {
+        let limit = args.next().map_or(100, |n| n.parse().unwrap());
+        for (i, game) in input.iter().enumerate() {
+            match Machine::from_game(game, Some(limit)).cheapest() {
+                Some((cost, presses)) => println!("machine {i}: {presses:?} for {cost} tokens"),
+                None => println!("machine {i}: can't be won"),
+            }
+        }
+        return;
+    }
+
     println!("Part 1: {}", part1(&input));
     println!("Part 2: {}", part2(input));
 }
@@ -189,14 +379,137 @@ mod tests {
             prize: Prize { x: 8400, y: 5400 },
         };
 
-        assert_eq!(Some(40), n_b(game));
-        assert_eq!(Some(80), n_a(game, 40));
+        assert_eq!(Some((80, 40)), solve(game));
 
         let game = Game {
             a: Button { x: 26, y: 66 },
             b: Button { x: 67, y: 21 },
             prize: Prize { x: 12748, y: 12176 },
         };
-        assert_eq!(None, n_b(game));
+        assert_eq!(None, solve(game));
+
+        // Used to divide by a.y
+        let game = Game {
+            a: Button { x: 5, y: 0 },
+            b: Button { x: 1, y: 3 },
+            prize: Prize { x: 12, y: 6 },
+        };
+        assert_eq!(Some((2, 2)), solve(game));
+    }
+
+    #[test]
+    fn test_part2_offset() {
+        let game = Game {
+            a: Button { x: 26, y: 66 },
+            b: Button { x: 67, y: 21 },
+            prize: Prize {
+                x: 10000000012748,
+                y: 10000000012176,
+            },
+        };
+        assert_eq!(Some((118679050709, 103199174542)), solve(game));
+    }
+
+    #[test]
+    fn test_collinear() {
+        // b is cheaper per step, so use as much b as possible
+        let game = Game {
+            a: Button { x: 2, y: 4 },
+            b: Button { x: 3, y: 6 },
+            prize: Prize { x: 13, y: 26 },
+        };
+        assert_eq!(Some((2, 3)), solve(game));
+        assert_eq!(game.min_cost(), game.cost());
+
+        // a moves more than 3 times as far as b, so use as much a as possible
+        let game = Game {
+            a: Button { x: 7, y: 7 },
+            b: Button { x: 2, y: 2 },
+            prize: Prize { x: 25, y: 25 },
+        };
+        assert_eq!(Some((3, 2)), solve(game));
+        assert_eq!(game.min_cost(), game.cost());
+
+        // Off the line
+        let game = Game {
+            a: Button { x: 1, y: 2 },
+            b: Button { x: 2, y: 4 },
+            prize: Prize { x: 5, y: 5 },
+        };
+        assert_eq!(None, solve(game));
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        for ax in 1..6 {
+            for ay in 1..6 {
+                for bx in 1..6 {
+                    for by in 1..6 {
+                        for (px, py) in [(12, 12), (20, 30), (17, 9), (24, 36)] {
+                            let game = Game {
+                                a: Button { x: ax, y: ay },
+                                b: Button { x: bx, y: by },
+                                prize: Prize { x: px, y: py },
+                            };
+                            assert_eq!(game.min_cost(), game.cost(), "{game:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cheapest() {
+        let game = Game {
+            a: Button { x: 94, y: 34 },
+            b: Button { x: 22, y: 67 },
+            prize: Prize { x: 8400, y: 5400 },
+        };
+        assert_eq!(
+            Some((280, vec![80, 40])),
+            Machine::from_game(&game, Some(100)).cheapest()
+        );
+        assert_eq!(None, Machine::from_game(&game, Some(50)).cheapest());
+
+        // A third button doing the work of both others for the price of one
+        let mut machine = Machine::from_game(&game, None);
+        machine.controls.push(Control {
+            button: Button { x: 116, y: 101 },
+            cost: 3,
+            limit: None,
+        });
+        machine.prize = Prize { x: 4640, y: 4040 };
+        assert_eq!(Some((120, vec![0, 0, 40])), machine.cheapest());
+
+        machine.controls[2].limit = Some(10);
+        let (cost, presses) = machine.cheapest().unwrap();
+        assert_eq!(4640, presses[0] * 94 + presses[1] * 22 + presses[2] * 116);
+        assert_eq!(4040, presses[0] * 34 + presses[1] * 67 + presses[2] * 101);
+        assert_eq!(3 * presses[0] + presses[1] + 3 * presses[2], cost);
+        assert!(presses[2] <= 10);
+    }
+
+    #[test]
+    fn test_cheapest_against_solve() {
+        for ax in 0..5 {
+            for ay in 0..5 {
+                for bx in 0..5 {
+                    for by in 0..5 {
+                        for (px, py) in [(0, 0), (12, 12), (20, 30), (17, 9), (24, 0)] {
+                            let game = Game {
+                                a: Button { x: ax, y: ay },
+                                b: Button { x: bx, y: by },
+                                prize: Prize { x: px, y: py },
+                            };
+                            let cheapest = Machine::from_game(&game, None)
+                                .cheapest()
+                                .map(|(cost, _)| cost);
+                            assert_eq!(cheapest, game.cost(), "{game:?}");
+                        }
+                    }
+                }
+            }
+        }
     }
 }
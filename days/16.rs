@@ -114,10 +114,7 @@ enum Cell {
 
 impl Cell {
     fn walkable(&self) -> bool {
-        match self {
-            Cell::Wall => false,
-            _ => true,
-        }
+        !matches!(self, Cell::Wall)
     }
 }
 
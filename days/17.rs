@@ -66,7 +66,7 @@ impl Computer {
             4 => "a",
             5 => "b",
             6 => "c",
-            x => panic!(),
+            _ => panic!(),
         }
     }
     fn disasm(&self) {
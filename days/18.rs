@@ -2,16 +2,102 @@ use std::collections::{BinaryHeap, HashMap};
 
 use grid::Grid;
 
-fn parse_input() -> Vec<(usize, usize)> {
-    let input = include_str!("../inputs/18.txt");
+// Width and height of the memory space
+type Size = (usize, usize);
 
-    input
-        .lines()
+const DEFAULT_SIZE: Size = (71, 71);
+const DEFAULT_BYTES: usize = 1024;
+
+// The falling bytes as (x, y), and how many of them have fallen by the time we start walking
+struct Memory {
+    size: Size,
+    bytes: usize,
+    falls: Vec<(usize, usize)>,
+}
+
+impl Memory {
+    fn new(size: Size, bytes: usize, falls: Vec<(usize, usize)>) -> Result<Self, String> {
+        if size.0 == 0 || size.1 == 0 {
+            return Err(format!("the {}x{} memory space is empty", size.0, size.1));
+        }
+        if let Some((i, f)) = falls
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.0 >= size.0 || f.1 >= size.1)
+        {
+            return Err(format!(
+                "byte {i} at {},{} falls outside the {}x{} memory space",
+                f.0, f.1, size.0, size.1
+            ));
+        }
+        if bytes > falls.len() {
+            return Err(format!("only {} bytes fall, not {bytes}", falls.len()));
+        }
+
+        Ok(Self { size, bytes, falls })
+    }
+
+    // The memory space once the first `bytes` bytes have fallen, with a row per y
+    fn grid(&self, bytes: usize) -> Grid<Cell> {
+        let mut grid = Grid::with_capacity(self.size.1);
+        for _i in 0..self.size.1 {
+            grid.add_row(vec![Cell::Free; self.size.0]);
+        }
+
+        for f in &self.falls[0..bytes] {
+            grid.set(f.1, f.0, Cell::Blocked);
+        }
+
+        grid
+    }
+}
+
+fn parse_input(size: Option<Size>, bytes: Option<usize>) -> Result<Memory, String> {
+    parse(include_str!("../inputs/18.txt"), size, bytes)
+}
+
+// One `x,y` byte per line. The input can start with a `<width>x<height> <bytes>` header, like `7x7 12`
+// for the example, and a size or byte count given here wins over the header. Without either, it's the
+// puzzle's 71x71 space after 1024 bytes.
+fn parse(input: &str, size: Option<Size>, bytes: Option<usize>) -> Result<Memory, String> {
+    let mut lines = input.lines().peekable();
+    let mut header = (None, None);
+    if let Some(line) = lines.next_if(|l| !l.contains(',') && !l.trim().is_empty()) {
+        let Some((s, b)) = line.split_once(' ') else {
+            return Err(format!("expected `<width>x<height> <bytes>` in `{line}`"));
+        };
+        header = (Some(parse_size(s)?), Some(parse_count(b)?));
+    }
+
+    let falls = lines
+        .filter(|l| !l.trim().is_empty())
         .map(|l| {
-            let mut parts = l.split(',').map(|d| d.parse().unwrap());
-            (parts.next().unwrap(), parts.next().unwrap())
+            let (x, y) = l
+                .trim()
+                .split_once(',')
+                .ok_or_else(|| format!("expected `x,y` in `{l}`"))?;
+            Ok((parse_count(x)?, parse_count(y)?))
         })
-        .collect()
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Memory::new(
+        size.or(header.0).unwrap_or(DEFAULT_SIZE),
+        bytes.or(header.1).unwrap_or(DEFAULT_BYTES),
+        falls,
+    )
+}
+
+fn parse_size(s: &str) -> Result<Size, String> {
+    let (w, h) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected `<width>x<height>` in `{s}`"))?;
+    Ok((parse_count(w)?, parse_count(h)?))
+}
+
+fn parse_count(s: &str) -> Result<usize, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("`{s}` is not a number"))
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -73,37 +159,190 @@ impl PartialOrd for State {
     }
 }
 
-fn part1(falls: &[(usize, usize)]) -> usize {
-    let mut grid: Grid<Cell> = Grid::with_capacity(71);
-    for _i in 0..=70 {
-        grid.add_row(vec![Cell::Free; 71]);
+fn part1(memory: &Memory) -> usize {
+    shortest_path(&memory.grid(memory.bytes))
+}
+
+fn part2(memory: &Memory) -> (usize, usize) {
+    first_blocking(&memory.falls, memory.size).expect("the path never gets blocked")
+}
+
+// Union-find with path halving and union by rank
+struct DisjointSet {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl DisjointSet {
+    fn new(n: usize) -> Self {
+        Self {
+            parent: (0..n).collect(),
+            rank: vec![0; n],
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        match self.rank[a].cmp(&self.rank[b]) {
+            std::cmp::Ordering::Less => self.parent[a] = b,
+            std::cmp::Ordering::Greater => self.parent[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parent[b] = a;
+                self.rank[a] += 1;
+            }
+        }
     }
 
-    for f in &falls[0..1024] {
-        grid.set(f.0, f.1, Cell::Blocked);
+    fn connected(&mut self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
     }
+}
 
-    shortest_path(&grid)
+fn neighbours(pos: (usize, usize), size: Size) -> impl Iterator<Item = (usize, usize)> {
+    [Dir::N, Dir::E, Dir::S, Dir::W]
+        .into_iter()
+        .filter_map(move |dir| dir.ap(pos))
+        .filter(move |p| p.0 < size.0 && p.1 < size.1)
 }
 
-fn part2(falls: &[(usize, usize)]) -> (usize, usize) {
-    let mut grid: Grid<Cell> = Grid::with_capacity(71);
-    for _i in 0..=70 {
-        grid.add_row(vec![Cell::Free; 71]);
+// The first byte that cuts the start off from the exit, if any does. Every byte is dropped up
+// front, then they are lifted back out from the last one, joining each freed cell with its free
+// neighbours. The byte whose removal reconnects the start and end nodes is the one that blocked them.
+fn first_blocking(falls: &[(usize, usize)], size: Size) -> Option<(usize, usize)> {
+    let cells = size.0 * size.1;
+    let cell = |pos: (usize, usize)| pos.1 * size.0 + pos.0;
+    let (start, end) = (cells, cells + 1);
+
+    // A cell only frees up once its earliest byte is lifted
+    let mut fell_at = vec![None; cells];
+    for (i, f) in falls.iter().enumerate() {
+        fell_at[cell(*f)].get_or_insert(i);
     }
 
-    for f in &falls[0..1024] {
-        grid.set(f.0, f.1, Cell::Blocked);
+    let mut sets = DisjointSet::new(cells + 2);
+    let free = |sets: &mut DisjointSet, fell_at: &[Option<usize>], pos: (usize, usize)| {
+        for n in neighbours(pos, size) {
+            if fell_at[cell(n)].is_none() {
+                sets.union(cell(pos), cell(n));
+            }
+        }
+        if pos == (0, 0) {
+            sets.union(cell(pos), start);
+        }
+        if pos == (size.0 - 1, size.1 - 1) {
+            sets.union(cell(pos), end);
+        }
+    };
+
+    for y in 0..size.1 {
+        for x in 0..size.0 {
+            if fell_at[cell((x, y))].is_none() {
+                free(&mut sets, &fell_at, (x, y));
+            }
+        }
+    }
+    if sets.connected(start, end) {
+        return None;
     }
 
-    let mut i = 1023;
+    for (i, f) in falls.iter().enumerate().rev() {
+        if fell_at[cell(*f)] != Some(i) {
+            continue;
+        }
+        fell_at[cell(*f)] = None;
+        free(&mut sets, &fell_at, *f);
+        if sets.connected(start, end) {
+            return Some(*f);
+        }
+    }
+
+    unreachable!("an empty memory space is always open")
+}
+
+// Answers whether the path is still open as bytes keep falling. Removing free cells isn't something
+// union-find can do, so it joins the fallen bytes instead, diagonals included, along with four nodes
+// for the sides of the memory space. The path is cut exactly when fallen bytes link the top or right
+// side to the bottom or left side.
+struct Barrier {
+    size: Size,
+    fallen: Vec<bool>,
+    sets: DisjointSet,
+    open: bool,
+}
+
+impl Barrier {
+    const TOP: usize = 0;
+    const RIGHT: usize = 1;
+    const BOTTOM: usize = 2;
+    const LEFT: usize = 3;
+
+    fn new(size: Size) -> Self {
+        Self {
+            size,
+            fallen: vec![false; size.0 * size.1],
+            sets: DisjointSet::new(size.0 * size.1 + 4),
+            open: true,
+        }
+    }
+
+    fn cell(&self, pos: (usize, usize)) -> usize {
+        pos.1 * self.size.0 + pos.0
+    }
+
+    // Drops a byte and tells whether the path is still open afterwards
+    fn drop(&mut self, pos: (usize, usize)) -> bool {
+        let cell = self.cell(pos);
+        self.fallen[cell] = true;
 
-    while shortest_path(&grid) > 0 {
-        i += 1;
-        grid.set(falls[i].0, falls[i].1, Cell::Blocked);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let (Some(x), Some(y)) =
+                    (pos.0.checked_add_signed(dx), pos.1.checked_add_signed(dy))
+                else {
+                    continue;
+                };
+                if x < self.size.0 && y < self.size.1 && self.fallen[self.cell((x, y))] {
+                    let other = self.cell((x, y));
+                    self.sets.union(4 + cell, 4 + other);
+                }
+            }
+        }
+
+        let sides = [
+            (pos.1 == 0, Self::TOP),
+            (pos.0 == self.size.0 - 1, Self::RIGHT),
+            (pos.1 == self.size.1 - 1, Self::BOTTOM),
+            (pos.0 == 0, Self::LEFT),
+        ];
+        for (touches, side) in sides {
+            if touches {
+                self.sets.union(4 + cell, side);
+            }
+        }
+
+        self.open = self.open
+            && ![Self::TOP, Self::RIGHT].into_iter().any(|a| {
+                [Self::BOTTOM, Self::LEFT]
+                    .into_iter()
+                    .any(|b| self.sets.connected(a, b))
+            });
+        self.open
     }
 
-    falls[i]
+    fn open(&self) -> bool {
+        self.open
+    }
 }
 
 fn h_cost(pos: (usize, usize), goal: (usize, usize)) -> usize {
@@ -113,7 +352,7 @@ fn h_cost(pos: (usize, usize), goal: (usize, usize)) -> usize {
 fn shortest_path(grid: &Grid<Cell>) -> usize {
     let mut to_visit = BinaryHeap::new();
     let start = (0, 0);
-    let goal = (70, 70);
+    let goal = (grid.row_count() - 1, grid.col_count() - 1);
     to_visit.push(State {
         pos: start,
         g_cost: 0,
@@ -164,9 +403,167 @@ fn shortest_path(grid: &Grid<Cell>) -> usize {
     // We failed
     0
 }
+
 pub fn main() {
-    let input = parse_input();
-    println!("Part 1: {}", part1(&input));
-    let p2 = part2(&input);
+    // `cargo run --bin 18 -- [--size <width>x<height>] [--bytes <n>] [--input <file>] [watch]`, where
+    // `watch` follows the path byte by byte until it closes
+    let (mut size, mut bytes, mut path, mut watch) = (None, None, None, false);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .unwrap_or_else(|| panic!("missing value for {arg}"))
+        };
+        match arg.as_str() {
+            "--size" => size = Some(parse_size(&value()).unwrap()),
+            "--bytes" => bytes = Some(parse_count(&value()).unwrap()),
+            "--input" => path = Some(value()),
+            "watch" => watch = true,
+            _ => panic!("unknown argument {arg}"),
+        }
+    }
+
+    let memory = match path {
+        Some(path) => parse(&std::fs::read_to_string(&path).unwrap(), size, bytes)
+            .unwrap_or_else(|e| panic!("{path}: {e}")),
+        None => parse_input(size, bytes).unwrap(),
+    };
+
+    if watch {
+        let mut barrier = Barrier::new(memory.size);
+        for (i, f) in memory.falls.iter().enumerate() {
+            let open = barrier.drop(*f);
+            println!(
+                "{i}: {},{} {}",
+                f.0,
+                f.1,
+                if open { "open" } else { "closed" }
+            );
+            if !barrier.open() {
+                break;
+            }
+        }
+        return;
+    }
+
+    println!("Part 1: {}", part1(&memory));
+    let p2 = part2(&memory);
     println!("Part 2: {},{}", p2.0, p2.1);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "7x7 12
+5,4
+4,2
+4,5
+3,0
+2,1
+6,3
+2,4
+1,5
+0,6
+3,3
+2,6
+5,1
+1,2
+5,5
+2,5
+6,5
+1,4
+0,4
+6,4
+1,1
+6,1
+1,0
+0,5
+1,6
+2,0
+";
+
+    fn open_after(memory: &Memory, bytes: usize) -> bool {
+        shortest_path(&memory.grid(bytes)) > 0
+    }
+
+    #[test]
+    fn test_example() {
+        let memory = parse(EXAMPLE, None, None).unwrap();
+        assert_eq!((7, 7), memory.size);
+        assert_eq!(12, memory.bytes);
+        assert_eq!(22, part1(&memory));
+        assert_eq!((6, 1), part2(&memory));
+
+        // Options win over the header
+        let memory = parse(EXAMPLE, Some((8, 7)), Some(20)).unwrap();
+        assert_eq!(((8, 7), 20), (memory.size, memory.bytes));
+        assert_eq!(19, part1(&memory));
+
+        let falls = EXAMPLE.split_once('\n').unwrap().1;
+        let memory = parse(falls, None, Some(25)).unwrap();
+        assert_eq!((DEFAULT_SIZE, 25), (memory.size, memory.bytes));
+        assert!(parse(falls, None, None).is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(
+            Err("byte 5 at 6,3 falls outside the 6x7 memory space".to_string()),
+            parse(EXAMPLE, Some((6, 7)), None).map(|_| ())
+        );
+        assert_eq!(
+            Err("only 25 bytes fall, not 30".to_string()),
+            parse(EXAMPLE, None, Some(30)).map(|_| ())
+        );
+        assert!(parse("7x7\n1,1", None, None).is_err());
+        assert!(parse("7x7 12\n1;1", None, None).is_err());
+        assert!(parse("0x7 0", None, None).is_err());
+    }
+
+    #[test]
+    fn test_first_blocking() {
+        let memory = parse(EXAMPLE, None, None).unwrap();
+        assert_eq!(Some((6, 1)), first_blocking(&memory.falls, (7, 7)));
+        assert_eq!(None, first_blocking(&memory.falls[..20], (7, 7)));
+
+        // Blocking the start itself, and a byte landing twice
+        assert_eq!(
+            Some((0, 0)),
+            first_blocking(&[(1, 1), (0, 0), (0, 0)], (3, 3))
+        );
+        assert_eq!(
+            Some((1, 0)),
+            first_blocking(&[(0, 1), (0, 1), (1, 0), (0, 1)], (3, 3))
+        );
+
+        // A wall across a wide space
+        assert_eq!(
+            Some((3, 1)),
+            first_blocking(&[(3, 0), (1, 1), (3, 2), (3, 1), (2, 1)], (5, 3))
+        );
+    }
+
+    #[test]
+    fn test_barrier() {
+        let memory = parse(EXAMPLE, None, None).unwrap();
+        let mut barrier = Barrier::new(memory.size);
+        for (i, f) in memory.falls.iter().enumerate() {
+            assert_eq!(open_after(&memory, i + 1), barrier.drop(*f), "after {f:?}");
+        }
+        assert!(!barrier.open());
+
+        // Cutting off a corner isn't enough unless the start or end is in it
+        let mut barrier = Barrier::new((3, 3));
+        assert!(barrier.drop((1, 0)));
+        assert!(barrier.drop((2, 1)));
+        assert!(!barrier.drop((0, 1)));
+
+        let falls = vec![(3, 0), (2, 2), (3, 2), (3, 1), (2, 1)];
+        let memory = Memory::new((5, 3), 0, falls.clone()).unwrap();
+        let mut barrier = Barrier::new(memory.size);
+        for (i, f) in falls.iter().enumerate() {
+            assert_eq!(open_after(&memory, i + 1), barrier.drop(*f), "after {f:?}");
+        }
+    }
+}
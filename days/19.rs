@@ -1,24 +1,26 @@
 use std::collections::HashMap;
 
-use regex::Regex;
-
 struct Input {
     patterns: Vec<String>,
     towels: Vec<String>,
 }
 
 fn parse_input() -> Input {
-    let input = include_str!("../inputs/19.txt");
+    parse(include_str!("../inputs/19.txt"))
+}
+
+fn parse(input: &str) -> Input {
     let mut parts = input.split("\n\n");
     let patterns = parts
         .next()
         .unwrap()
+        .trim()
         .split(", ")
         .map(|x| x.to_string())
         .collect();
     let towels = parts
         .next()
-        .unwrap()
+        .unwrap_or_default()
         .lines()
         .map(|x| x.to_string())
         .collect();
@@ -26,16 +28,186 @@ fn parse_input() -> Input {
     Input { patterns, towels }
 }
 
-fn part1(input: &Input) -> usize {
-    let mut pats = input.patterns.clone();
-    for pat in pats.iter_mut() {
-        *pat = format!("({pat})");
+#[derive(Debug, Default)]
+struct Node {
+    next: HashMap<u8, usize>,
+    pattern: Option<usize>,
+}
+
+// A trie over the towel patterns. Walking a design from some position down the trie finds every
+// pattern that starts there in at most (longest pattern) steps, so the whole design is covered by a
+// DP over its positions instead of trying each pattern at each suffix.
+#[derive(Debug)]
+struct Towels {
+    patterns: Vec<String>,
+    nodes: Vec<Node>,
+}
+
+impl Towels {
+    fn new(patterns: &[String]) -> Self {
+        let mut nodes = vec![Node::default()];
+        for (i, pattern) in patterns.iter().enumerate() {
+            let mut node = 0;
+            for b in pattern.bytes() {
+                node = match nodes[node].next.get(&b) {
+                    Some(n) => *n,
+                    None => {
+                        nodes.push(Node::default());
+                        let n = nodes.len() - 1;
+                        nodes[node].next.insert(b, n);
+                        n
+                    }
+                };
+            }
+            if !pattern.is_empty() {
+                nodes[node].pattern = Some(i);
+            }
+        }
+
+        Self {
+            patterns: patterns.to_vec(),
+            nodes,
+        }
+    }
+
+    // The patterns that `design` starts with, shortest first
+    fn prefixes<'a>(&'a self, design: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        design
+            .iter()
+            .scan(0, |node, b| {
+                *node = *self.nodes[*node].next.get(b)?;
+                Some(self.nodes[*node].pattern)
+            })
+            .flatten()
+    }
+
+    // ways[i] is the number of arrangements of design[i..]
+    fn ways_from(&self, design: &[u8]) -> Vec<usize> {
+        let mut ways = vec![0; design.len() + 1];
+        ways[design.len()] = 1;
+        for i in (0..design.len()).rev() {
+            ways[i] = self
+                .prefixes(&design[i..])
+                .map(|p| ways[i + self.patterns[p].len()])
+                .sum();
+        }
+        ways
+    }
+
+    fn ways(&self, design: &str) -> usize {
+        self.ways_from(design.as_bytes())[0]
+    }
+
+    fn possible(&self, design: &str) -> bool {
+        self.ways(design) > 0
+    }
+
+    // Every arrangement of the design, in pattern order. Positions that can't be finished are never
+    // entered, so each step of the walk is on the way to an arrangement.
+    fn arrangements<'a>(&'a self, design: &'a str) -> Arrangements<'a> {
+        let design = design.as_bytes();
+        let mut arrangements = Arrangements {
+            towels: self,
+            design,
+            ways: self.ways_from(design),
+            stack: Vec::new(),
+            chosen: Vec::new(),
+        };
+        if arrangements.ways[0] > 0 {
+            let candidates = arrangements.candidates(0);
+            arrangements.stack.push((0, candidates));
+        }
+        arrangements
+    }
+
+    // An arrangement using as few towels as possible
+    fn fewest(&self, design: &str) -> Option<Vec<&str>> {
+        let design = design.as_bytes();
+        // best[i] is the fewest towels for design[i..] and the first one to use
+        let mut best: Vec<Option<(usize, usize)>> = vec![None; design.len() + 1];
+        best[design.len()] = Some((0, usize::MAX));
+        for i in (0..design.len()).rev() {
+            best[i] = self
+                .prefixes(&design[i..])
+                .filter_map(|p| best[i + self.patterns[p].len()].map(|(n, _)| (n + 1, p)))
+                .min_by_key(|(n, _)| *n);
+        }
+
+        best[0]?;
+        let mut arrangement = Vec::new();
+        let mut i = 0;
+        while i < design.len() {
+            let (_, p) = best[i].unwrap();
+            arrangement.push(self.patterns[p].as_str());
+            i += self.patterns[p].len();
+        }
+        Some(arrangement)
+    }
+}
+
+struct Arrangements<'a> {
+    towels: &'a Towels,
+    design: &'a [u8],
+    ways: Vec<usize>,
+    // Each position reached along with the patterns still to try there
+    stack: Vec<(usize, Vec<usize>)>,
+    chosen: Vec<usize>,
+}
+
+impl Arrangements<'_> {
+    // The patterns at `pos` that leave something that can still be arranged, to be popped in order
+    fn candidates(&self, pos: usize) -> Vec<usize> {
+        let mut candidates = self
+            .towels
+            .prefixes(&self.design[pos..])
+            .filter(|p| self.ways[pos + self.towels.patterns[*p].len()] > 0)
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by(|a, b| b.cmp(a));
+        candidates
     }
-    let re_str = format!("^({})+$", pats.join("|"));
-    let re = Regex::new(&re_str).unwrap();
-    input.towels.iter().filter(|t| re.is_match(t)).count()
 }
 
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let towels = self.towels;
+        loop {
+            let (pos, candidates) = self.stack.last_mut()?;
+            let pos = *pos;
+            if pos == self.design.len() {
+                let arrangement = self
+                    .chosen
+                    .iter()
+                    .map(|p| towels.patterns[*p].as_str())
+                    .collect();
+                self.stack.pop();
+                self.chosen.pop();
+                return Some(arrangement);
+            }
+
+            match candidates.pop() {
+                None => {
+                    self.stack.pop();
+                    self.chosen.pop();
+                }
+                Some(p) => {
+                    self.chosen.push(p);
+                    let next = pos + towels.patterns[p].len();
+                    let candidates = self.candidates(next);
+                    self.stack.push((next, candidates));
+                }
+            }
+        }
+    }
+}
+
+fn part1(input: &Input) -> usize {
+    let towels = Towels::new(&input.patterns);
+    input.towels.iter().filter(|t| towels.possible(t)).count()
+}
+
+#[cfg(test)]
 fn waysto(input: &str, patterns: &Vec<String>, cache: &mut HashMap<String, usize>) -> usize {
     if input.is_empty() {
         return 1;
@@ -57,23 +229,124 @@ fn waysto(input: &str, patterns: &Vec<String>, cache: &mut HashMap<String, usize
 }
 
 fn part2(input: &Input) -> usize {
-    let mut count = 0;
+    let towels = Towels::new(&input.patterns);
+    input.towels.iter().map(|t| towels.ways(t)).sum()
+}
 
-    let mut pats = input.patterns.clone();
-    pats.sort_by_key(|f| f.len());
+pub fn main() {
+    let input = parse_input();
+
+    // `cargo run --bin 19 -- arrangements <design> [limit]` or `cargo run --bin 19 -- fewest <design>`
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("arrangements") => {
+            let design = args.next().expect("missing design");
+            let limit = args.next().map_or(usize::MAX, |n| n.parse().unwrap());
+            let towels = Towels::new(&input.patterns);
+            println!("{} arrangements", towels.ways(&design));
+            for arrangement in towels.arrangements(&design).take(limit) {
+                println!("{}", arrangement.join(" "));
+            }
+        }
+        Some("fewest") => {
+            let design = args.next().expect("missing design");
+            match Towels::new(&input.patterns).fewest(&design) {
+                Some(arrangement) => {
+                    println!("{} towels: {}", arrangement.len(), arrangement.join(" "))
+                }
+                None => println!("{design} can't be made"),
+            }
+        }
+        _ => {
+            println!("Part 1: {}", part1(&input));
+            println!("Part 2: {}", part2(&input));
+        }
+    }
+}
 
-    let mut cache: HashMap<String, usize> = HashMap::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for towel in input.towels.iter() {
-        println!("Checking {towel}");
-        count += waysto(towel, &input.patterns, &mut cache);
+    const EXAMPLE: &str = "r, wr, b, g, bwu, rb, gb, br
+
+brwrr
+bggr
+gbbr
+rrbgbr
+ubwu
+bwurrg
+brgr
+bbrgwb
+";
+
+    #[test]
+    fn test_example() {
+        let input = parse(EXAMPLE);
+        assert_eq!(6, part1(&input));
+        assert_eq!(16, part2(&input));
+
+        let towels = Towels::new(&input.patterns);
+        let ways = input
+            .towels
+            .iter()
+            .map(|t| towels.ways(t))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![2, 1, 4, 6, 0, 1, 2, 0], ways);
     }
 
-    count
-}
+    #[test]
+    fn test_against_waysto() {
+        let patterns = ["a", "ab", "abc", "b", "bc", "c", "ca", "cab"]
+            .map(String::from)
+            .to_vec();
+        let towels = Towels::new(&patterns);
+        let mut cache = HashMap::new();
+        for design in ["", "abcabc", "cabcab", "abcd", "bbbcacab", "cccc"] {
+            assert_eq!(waysto(design, &patterns, &mut cache), towels.ways(design));
+        }
+    }
 
-pub fn main() {
-    let input = parse_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    #[test]
+    fn test_arrangements() {
+        let input = parse(EXAMPLE);
+        let towels = Towels::new(&input.patterns);
+
+        let gbbr = towels.arrangements("gbbr").collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                vec!["g", "b", "b", "r"],
+                vec!["g", "b", "br"],
+                vec!["gb", "b", "r"],
+                vec!["gb", "br"],
+            ],
+            gbbr
+        );
+
+        for design in &input.towels {
+            assert_eq!(towels.ways(design), towels.arrangements(design).count());
+            for arrangement in towels.arrangements(design) {
+                assert_eq!(*design, arrangement.concat());
+            }
+        }
+        assert_eq!(
+            vec![Vec::<&str>::new()],
+            towels.arrangements("").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_fewest() {
+        let input = parse(EXAMPLE);
+        let towels = Towels::new(&input.patterns);
+
+        assert_eq!(Some(vec!["gb", "br"]), towels.fewest("gbbr"));
+        assert_eq!(Some(vec!["bwu", "r", "r", "g"]), towels.fewest("bwurrg"));
+        assert_eq!(None, towels.fewest("ubwu"));
+
+        for design in &input.towels {
+            let fewest = towels.arrangements(design).map(|a| a.len()).min();
+            assert_eq!(fewest, towels.fewest(design).map(|a| a.len()));
+        }
+    }
 }
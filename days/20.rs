@@ -1,5 +1,5 @@
 use std::{
-    collections::{BinaryHeap, HashMap, HashSet},
+    collections::{BTreeMap, BinaryHeap, HashMap},
     fmt::{Display, Write as _},
 };
 
@@ -15,10 +15,7 @@ enum Cell {
 
 impl Cell {
     fn walkable(&self) -> bool {
-        match self {
-            Cell::Wall => false,
-            _ => true,
-        }
+        !matches!(self, Cell::Wall)
     }
 }
 
@@ -87,6 +84,9 @@ fn h_cost(pos: (usize, usize), goal: (usize, usize)) -> usize {
     pos.0.abs_diff(goal.0) + pos.1.abs_diff(goal.1)
 }
 
+type Pos = (usize, usize);
+type Visited = HashMap<Pos, (usize, Option<Pos>)>;
+
 fn shortest_path(grid: &Grid<Cell>) -> Vec<(usize, usize)> {
     let mut to_visit = BinaryHeap::new();
     let Some(start) = grid
@@ -113,7 +113,7 @@ fn shortest_path(grid: &Grid<Cell>) -> Vec<(usize, usize)> {
         prev: None,
     });
 
-    let mut visited: HashMap<(usize, usize), (usize, Option<(usize, usize)>)> = HashMap::new();
+    let mut visited: Visited = HashMap::new();
     while let Some(node) = to_visit.pop() {
         if node.pos == goal {
             let mut path = vec![node.pos];
@@ -167,6 +167,98 @@ fn shortest_path(grid: &Grid<Cell>) -> Vec<(usize, usize)> {
     Vec::new()
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cheat {
+    // The last track position before going through walls, and the first one back on the track
+    start: Pos,
+    end: Pos,
+    length: usize,
+    saving: usize,
+}
+
+impl Display for Cheat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{} -> {},{} in {} saves {}",
+            self.start.0, self.start.1, self.end.0, self.end.1, self.length, self.saving
+        )
+    }
+}
+
+// The race track, with every position indexed by how many steps in it is. A cheat of up to n steps
+// from a position can only land in the diamond of radius n around it, so that's all that gets looked
+// at, rather than every other position on the track.
+struct Track {
+    path: Vec<Pos>,
+    cols: usize,
+    rows: usize,
+    steps: Vec<Option<usize>>,
+}
+
+impl Track {
+    fn new(grid: &Grid<Cell>) -> Self {
+        let path = shortest_path(grid);
+        let (rows, cols) = (grid.row_count(), grid.col_count());
+        let mut steps = vec![None; rows * cols];
+        for (i, pos) in path.iter().enumerate() {
+            steps[pos.0 * cols + pos.1] = Some(i);
+        }
+
+        Self {
+            path,
+            cols,
+            rows,
+            steps,
+        }
+    }
+
+    // Every cheat through at most `max_length` steps that saves at least `min_saving`, by start and
+    // then end position along the track
+    fn cheats(&self, max_length: usize, min_saving: usize) -> Vec<Cheat> {
+        let mut cheats = Vec::new();
+        for (i, &start) in self.path.iter().enumerate() {
+            let (row, col) = (start.0 as isize, start.1 as isize);
+            let radius = max_length as isize;
+            for r in (row - radius).max(0)..=(row + radius).min(self.rows as isize - 1) {
+                let reach = radius - (r - row).abs();
+                for c in (col - reach).max(0)..=(col + reach).min(self.cols as isize - 1) {
+                    let end = (r as usize, c as usize);
+                    let Some(j) = self.steps[end.0 * self.cols + end.1] else {
+                        continue;
+                    };
+                    let length = h_cost(start, end);
+                    if j > i + length && j - i - length >= min_saving {
+                        cheats.push(Cheat {
+                            start,
+                            end,
+                            length,
+                            saving: j - i - length,
+                        });
+                    }
+                }
+            }
+        }
+
+        cheats.sort_by_key(|c| (self.step(c.start), self.step(c.end)));
+        cheats
+    }
+
+    fn step(&self, pos: Pos) -> Option<usize> {
+        self.steps[pos.0 * self.cols + pos.1]
+    }
+}
+
+// How many cheats save each amount of time
+fn histogram(cheats: &[Cheat]) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for cheat in cheats {
+        *histogram.entry(cheat.saving).or_default() += 1;
+    }
+    histogram
+}
+
+#[cfg(test)]
 fn skips(path: Vec<(usize, usize)>, skips_allowed: usize, savings: usize) -> usize {
     let mut ways = 0;
     for i in 0..path.len() {
@@ -182,17 +274,18 @@ fn skips(path: Vec<(usize, usize)>, skips_allowed: usize, savings: usize) -> usi
 }
 
 fn part1(grid: &Grid<Cell>) -> usize {
-    let benchmark = shortest_path(grid);
-    skips(benchmark, 2, 100)
+    Track::new(grid).cheats(2, 100).len()
 }
 
 fn part2(grid: &Grid<Cell>) -> usize {
-    let benchmark = shortest_path(grid);
-    skips(benchmark, 20, 100)
+    Track::new(grid).cheats(20, 100).len()
 }
 
 fn parse_input() -> Grid<Cell> {
-    let input = include_str!("../inputs/20.txt");
+    parse(include_str!("../inputs/20.txt"))
+}
+
+fn parse(input: &str) -> Grid<Cell> {
     let mut grid = Grid::new();
 
     for line in input.lines() {
@@ -214,7 +307,114 @@ fn parse_input() -> Grid<Cell> {
 
 pub fn main() {
     let grid = parse_input();
-    // println!("{}", grid);
-    println!("{}", part1(&grid));
-    println!("{}", part2(&grid));
+
+    // `cargo run --bin 20 -- histogram <length> <saving>` counts the cheats of up to `length` steps by
+    // how much they save, at least `saving`, and `list <length> <saving>` prints each of them
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some(command @ ("histogram" | "list")) => {
+            let mut number = || args.next().and_then(|n| n.parse().ok()).expect("number");
+            let (length, saving) = (number(), number());
+            let cheats = Track::new(&grid).cheats(length, saving);
+            if command == "list" {
+                for cheat in &cheats {
+                    println!("{cheat}");
+                }
+            } else {
+                for (saving, count) in histogram(&cheats) {
+                    println!("{count} cheats save {saving} picoseconds");
+                }
+            }
+        }
+        _ => {
+            println!("{}", part1(&grid));
+            println!("{}", part2(&grid));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "###############
+#...#...#.....#
+#.#.#.#.#.###.#
+#S#...#.#.#...#
+#######.#.#.###
+#######.#.#...#
+#######.#.###.#
+###..E#...#...#
+###.#######.###
+#...###...#...#
+#.#####.#.###.#
+#.#...#.#.#...#
+#.#.#.#.#.#.###
+#...#...#...###
+###############
+";
+
+    #[test]
+    fn test_histogram() {
+        let track = Track::new(&parse(EXAMPLE));
+        assert_eq!(85, track.path.len());
+
+        let expected = [
+            (2, 14),
+            (4, 14),
+            (6, 2),
+            (8, 4),
+            (10, 2),
+            (12, 3),
+            (20, 1),
+            (36, 1),
+            (38, 1),
+            (40, 1),
+            (64, 1),
+        ];
+        assert_eq!(BTreeMap::from(expected), histogram(&track.cheats(2, 1)));
+
+        let expected = [
+            (50, 32),
+            (52, 31),
+            (54, 29),
+            (56, 39),
+            (58, 25),
+            (60, 23),
+            (62, 20),
+            (64, 19),
+            (66, 12),
+            (68, 14),
+            (70, 12),
+            (72, 22),
+            (74, 4),
+            (76, 3),
+        ];
+        assert_eq!(BTreeMap::from(expected), histogram(&track.cheats(20, 50)));
+    }
+
+    #[test]
+    fn test_cheats() {
+        let track = Track::new(&parse(EXAMPLE));
+        let best = track.cheats(2, 64);
+        assert_eq!(
+            vec![Cheat {
+                start: (7, 7),
+                end: (7, 5),
+                length: 2,
+                saving: 64
+            }],
+            best
+        );
+        assert_eq!("7,7 -> 7,5 in 2 saves 64", best[0].to_string());
+
+        for (length, saving) in [(2, 1), (2, 10), (6, 20), (20, 50), (30, 1)] {
+            let cheats = track.cheats(length, saving);
+            assert_eq!(skips(track.path.clone(), length, saving), cheats.len());
+            assert!(cheats
+                .iter()
+                .all(|c| c.length <= length && c.saving >= saving));
+            assert!(cheats.is_sorted_by_key(|c| (track.step(c.start), track.step(c.end))));
+        }
+    }
 }
@@ -1,11 +1,12 @@
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
     fmt::{Display, Write},
 };
 
 use grid::Grid;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 enum Button {
     Num(u8),
     Up,
@@ -14,18 +15,8 @@ enum Button {
     Right,
     Activate,
     Empty,
-}
-
-impl Button {
-    fn ap(&self, pos: (usize, usize)) -> (usize, usize) {
-        match self {
-            Button::Up => (pos.0 - 1, pos.1),
-            Button::Down => (pos.0 + 1, pos.1),
-            Button::Left => (pos.0, pos.1 - 1),
-            Button::Right => (pos.0, pos.1 + 1),
-            _ => pos,
-        }
-    }
+    // Anything else a layout names with a single character
+    Key(char),
 }
 
 impl Display for Button {
@@ -38,165 +29,144 @@ impl Display for Button {
             Button::Right => f.write_char('>'),
             Button::Activate => f.write_char('A'),
             Button::Empty => f.write_char(' '),
+            Button::Key(ch) => f.write_char(*ch),
         }
     }
 }
 
+// Keypads are drawn one row per line, one character per button. Digits, `^`, `v`, `<`, `>` and `A`
+// are what they look like, a space or `#` is a gap the arm must never hover over, and any other
+// character is a button of that name. Every keypad needs an `A`, which is where its arm starts.
+const NUMERIC: &str = "
+789
+456
+123
+ 0A
+";
+
+const DIRECTIONAL: &str = "
+ ^A
+<v>
+";
+
 #[derive(Debug, Clone)]
 struct Keypad {
     pos: (usize, usize),
     grid: Grid<Button>,
+    buttons: HashMap<Button, (usize, usize)>,
+    gaps: Vec<(usize, usize)>,
 }
 
-fn keypads() -> Vec<Keypad> {
-    vec![
-        Keypad {
-            grid: numeric(),
-            pos: (3, 2),
-        },
-        Keypad {
-            grid: directional(),
-            pos: (0, 2),
-        },
-        Keypad {
-            grid: directional(),
-            pos: (0, 2),
-        },
-    ]
-}
-
-fn keypads_n(n: usize) -> Vec<Keypad> {
-    let mut pads = Vec::with_capacity(1 + n);
-
-    pads.push(Keypad {
-        grid: numeric(),
-        pos: (3, 2),
-    });
+impl Keypad {
+    fn parse(layout: &str) -> Result<Self, String> {
+        let rows = layout
+            .lines()
+            .map(|l| l.trim_end())
+            .skip_while(|l| l.is_empty())
+            .collect::<Vec<_>>();
+        let rows = &rows[..rows
+            .iter()
+            .rposition(|l| !l.is_empty())
+            .map_or(0, |i| i + 1)];
+        let width = rows
+            .iter()
+            .map(|r| r.chars().count())
+            .max()
+            .unwrap_or_default();
+
+        let mut grid = Grid::with_capacity(rows.len());
+        let mut buttons = HashMap::new();
+        let mut gaps = Vec::new();
+        for (r, row) in rows.iter().enumerate() {
+            let mut cells = row.chars().map(parse_button).collect::<Vec<_>>();
+            cells.resize(width, Button::Empty);
+            for (c, button) in cells.iter().enumerate() {
+                if *button == Button::Empty {
+                    gaps.push((r, c));
+                } else if buttons.insert(*button, (r, c)).is_some() {
+                    return Err(format!("`{button}` appears twice in the layout"));
+                }
+            }
+            grid.add_row(cells);
+        }
 
-    for _i in 0..n {
-        pads.push(Keypad {
-            grid: directional(),
-            pos: (0, 2),
-        });
+        let Some(pos) = buttons.get(&Button::Activate).copied() else {
+            return Err("the layout has no `A` button".to_string());
+        };
+        Ok(Self {
+            pos,
+            grid,
+            buttons,
+            gaps,
+        })
     }
-    pads
-}
 
-fn directional() -> Grid<Button> {
-    let mut grid = Grid::new();
-    grid.add_row(vec![Button::Empty, Button::Up, Button::Activate]);
-    grid.add_row(vec![Button::Left, Button::Down, Button::Right]);
-
-    grid
-}
-
-fn numeric() -> Grid<Button> {
-    let mut grid = Grid::new();
-    grid.add_row(vec![Button::Num(7), Button::Num(8), Button::Num(9)]);
-    grid.add_row(vec![Button::Num(4), Button::Num(5), Button::Num(6)]);
-    grid.add_row(vec![Button::Num(1), Button::Num(2), Button::Num(3)]);
-    grid.add_row(vec![Button::Empty, Button::Num(0), Button::Activate]);
-
-    grid
-}
-
-fn find_button(grid: &Grid<Button>, button: Button) -> Option<(usize, usize)> {
-    grid.iter_cells()
-        .find(|c| *c.val == button)
-        .map(|c| (c.row, c.col))
-}
-
-fn moves_between(
-    start: (usize, usize),
-    goal: (usize, usize),
-    avoid: (usize, usize),
-) -> ((usize, Button), (usize, Button)) {
-    let v_dist: isize = goal.0 as isize - start.0 as isize;
-    let h_dist: isize = goal.1 as isize - start.1 as isize;
-
-    let vertical = if v_dist < 0 {
-        (v_dist.unsigned_abs(), Button::Up)
-    } else {
-        (v_dist as usize, Button::Down)
-    };
-
-    let horizontal = if h_dist < 0 {
-        (h_dist.unsigned_abs(), Button::Left)
-    } else {
-        (h_dist as usize, Button::Right)
-    };
-
-    reorder(start, avoid, (vertical, horizontal))
-}
-
-fn reorder(
-    start: (usize, usize),
-    avoid: (usize, usize),
-    moves: ((usize, Button), (usize, Button)),
-) -> ((usize, Button), (usize, Button)) {
-    // Test if taking the first button first goes
-    if test_avoid(start, moves.0 .1, moves.0 .0, avoid)
-        .is_none_or(|start| test_avoid(start, moves.1 .1, moves.1 .0, avoid).is_none())
-    {
-        return (moves.1, moves.0);
+    fn find(&self, button: Button) -> Option<(usize, usize)> {
+        self.buttons.get(&button).copied()
     }
 
-    if test_avoid(start, moves.1 .1, moves.1 .0, avoid)
-        .is_none_or(|start| test_avoid(start, moves.0 .1, moves.0 .0, avoid).is_none())
-    {
-        return moves;
+    // Where the arm ends up after pressing an arrow, unless that takes it off the keypad or over a gap
+    fn step(&self, pos: (usize, usize), arrow: Button) -> Option<(usize, usize)> {
+        self.step_or_fault(pos, arrow).ok()
     }
 
-    // If left, prefer going left-right to up-down
-    if moves.1 .1 == Button::Left {
-        return (moves.1, moves.0);
+    fn step_or_fault(&self, pos: (usize, usize), arrow: Button) -> Result<(usize, usize), Fault> {
+        let next = match arrow {
+            Button::Up => pos.0.checked_sub(1).map(|row| (row, pos.1)),
+            Button::Down => Some((pos.0 + 1, pos.1)),
+            Button::Left => pos.1.checked_sub(1).map(|col| (pos.0, col)),
+            Button::Right => Some((pos.0, pos.1 + 1)),
+            _ => return Err(Fault::NotAnArrow),
+        };
+        match next {
+            Some(next) if self.gaps.contains(&next) => Err(Fault::OverGap),
+            Some(next) if self.grid.get(next.0, next.1).is_some() => Ok(next),
+            _ => Err(Fault::OffKeypad),
+        }
     }
+}
 
-    // Otherwise, prefer going updown then left-right
-    moves
+impl Display for Keypad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.grid)
+    }
 }
 
-fn test_avoid(
-    start: (usize, usize),
-    button: Button,
-    times: usize,
-    avoid: (usize, usize),
-) -> Option<(usize, usize)> {
-    let mut pos = start;
-    for _i in 0..times {
-        pos = button.ap(pos);
-        if pos == avoid {
-            return None;
+// The keypads from the one typing the code to the one the human presses, each robot being driven by
+// the keypad after it. Every keypad but the first has to be able to drive an arm.
+fn chain(layouts: &[&str]) -> Result<Vec<Keypad>, String> {
+    let keypads = layouts
+        .iter()
+        .map(|l| Keypad::parse(l))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let arrows = [Button::Up, Button::Down, Button::Left, Button::Right];
+    for (i, keypad) in keypads.iter().enumerate().skip(1) {
+        if let Some(missing) = arrows.iter().find(|b| keypad.find(**b).is_none()) {
+            return Err(format!("keypad {i} can't drive an arm without `{missing}`"));
         }
     }
 
-    Some(pos)
+    Ok(keypads)
 }
 
-fn part1(input: &Vec<Vec<Button>>) -> usize {
-    let mut score = 0;
-
-    for code in input {
-        let mut keypads = keypads();
-        let mut target = code.clone();
-        for kp in keypads.iter_mut() {
-            target = expand(target, kp);
-        }
-        println!("{}", target.len());
-        score += target.len() * val(code);
-    }
+fn keypads() -> Vec<Keypad> {
+    chain(&[NUMERIC, DIRECTIONAL, DIRECTIONAL]).unwrap()
+}
 
-    score
+fn keypads_n(n: usize) -> Vec<Keypad> {
+    let mut layouts = vec![NUMERIC];
+    layouts.extend(std::iter::repeat_n(DIRECTIONAL, n));
+    chain(&layouts).unwrap()
 }
 
-fn part1_opt(input: &Vec<Vec<Button>>) -> usize {
+fn part1(input: &Vec<Vec<Button>>) -> usize {
     let mut score = 0;
 
     let mut cache = HashMap::new();
     for code in input {
         let keypads = keypads();
         let res = expand_count(code, 0, &keypads, &mut cache);
-        println!("{res}");
         score += res * val(code);
     }
 
@@ -217,7 +187,8 @@ fn part2(input: &Vec<Vec<Button>>) -> usize {
 }
 
 type Pos = (usize, usize);
-type MoveCache = HashMap<(Pos, Pos, Pos, usize), usize>;
+// The fewest presses for each move of an arm, and the presses that make it on the keypad driving it
+type MoveCache = HashMap<(Pos, Pos, usize), (usize, Vec<Button>)>;
 
 fn expand_count(
     target: &[Button],
@@ -232,57 +203,106 @@ fn expand_count(
     let keypad = &keypads[0];
 
     let mut sum = 0;
-    let avoid = find_button(&keypad.grid, Button::Empty).unwrap();
     let mut pos = keypad.pos;
     for button in target.iter() {
-        let mut moves = vec![];
-
-        let goal = find_button(&keypad.grid, *button).unwrap();
-        if let Some(cached) = move_cache.get(&(pos, goal, avoid, depth)) {
-            sum += *cached;
-        } else {
-            let (mv_a, mv_b) = moves_between(pos, goal, avoid);
-            for _i in 0..mv_a.0 {
-                moves.push(mv_a.1);
-            }
-            for _i in 0..mv_b.0 {
-                moves.push(mv_b.1);
-            }
-
-            moves.push(Button::Activate);
-
-            let count = expand_count(&moves, depth + 1, &keypads[1..], move_cache);
-            move_cache.insert((pos, goal, avoid, depth), count);
-
-            sum += count;
-        }
+        let goal = keypad
+            .find(*button)
+            .unwrap_or_else(|| panic!("no `{button}` on keypad {depth}"));
+        sum += move_cost(pos, goal, depth, keypads, move_cache);
         pos = goal;
     }
 
     sum
 }
 
-fn expand(target: Vec<Button>, keypad: &mut Keypad) -> Vec<Button> {
-    let mut moves = vec![];
-    let init = keypad.pos;
-    let avoid = find_button(&keypad.grid, Button::Empty).unwrap();
-    for button in target {
-        let goal = find_button(&keypad.grid, button).unwrap();
-        let (mv_a, mv_b) = moves_between(keypad.pos, goal, avoid);
-        for _i in 0..mv_a.0 {
-            moves.push(mv_a.1);
-        }
-        for _i in 0..mv_b.0 {
-            moves.push(mv_b.1);
+const PRESSES: [Button; 5] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::Activate,
+];
+
+// The fewest human presses for the arm over keypads[0] to go from `start` to `goal` and press it.
+// Whenever a keypad's button gets pressed, every arm further down the chain is back on its `A`, so
+// what a press costs only depends on the button the next arm was on before it. That makes this a
+// shortest path over (arm position, next arm's button), which weighs every order and interleaving of
+// the arrows, and detours around gaps too, against the real cost of pressing them.
+fn move_cost(
+    start: Pos,
+    goal: Pos,
+    depth: usize,
+    keypads: &[Keypad],
+    move_cache: &mut MoveCache,
+) -> usize {
+    if let Some((cached, _)) = move_cache.get(&(start, goal, depth)) {
+        return *cached;
+    }
+
+    let keypad = &keypads[0];
+    let press = |from: usize, to: usize, move_cache: &mut MoveCache| match keypads.get(1) {
+        None => 1,
+        Some(next) => {
+            let find = |b: Button| {
+                next.find(b)
+                    .unwrap_or_else(|| panic!("no `{b}` on keypad {}", depth + 1))
+            };
+            move_cost(
+                find(PRESSES[from]),
+                find(PRESSES[to]),
+                depth + 1,
+                &keypads[1..],
+                move_cache,
+            )
         }
+    };
 
-        moves.push(Button::Activate);
-        keypad.pos = goal;
-    }
+    // States are (presses, pressed goal, arm position, index of the next arm's button), along with
+    // the state they were reached from
+    let mut to_visit = BinaryHeap::from([Reverse((0, false, start, 4, None))]);
+    let mut came_from = HashMap::new();
+    while let Some(Reverse((cost, done, pos, prev, from))) = to_visit.pop() {
+        if done {
+            let mut presses = vec![Button::Activate];
+            let mut state = from;
+            while let Some((pos, prev)) = state.filter(|s| *s != (start, 4)) {
+                presses.push(PRESSES[prev]);
+                state = came_from[&(pos, prev)];
+            }
+            presses.reverse();
 
-    assert_eq!(init, keypad.pos);
+            move_cache.insert((start, goal, depth), (cost, presses));
+            return cost;
+        }
+        if came_from.contains_key(&(pos, prev)) {
+            continue;
+        }
+        came_from.insert((pos, prev), from);
+
+        let here = Some((pos, prev));
+        if pos == goal {
+            to_visit.push(Reverse((
+                cost + press(prev, 4, move_cache),
+                true,
+                pos,
+                4,
+                here,
+            )));
+        }
+        for (i, arrow) in PRESSES[..4].iter().enumerate() {
+            if let Some(next) = keypad.step(pos, *arrow) {
+                to_visit.push(Reverse((
+                    cost + press(prev, i, move_cache),
+                    false,
+                    next,
+                    i,
+                    here,
+                )));
+            }
+        }
+    }
 
-    moves
+    panic!("the arm over keypad {depth} can't get from {start:?} to {goal:?}")
 }
 
 fn parse_input() -> Vec<Vec<Button>> {
@@ -304,83 +324,629 @@ fn val(buttons: &[Button]) -> usize {
 }
 
 fn parse_buttons(input: &str) -> Vec<Button> {
-    input
-        .chars()
-        .map(|ch| match ch {
-            '0'..='9' => Button::Num(ch.to_digit(10).unwrap() as u8),
-            'A' => Button::Activate,
-            'v' => Button::Down,
-            '^' => Button::Up,
-            '<' => Button::Left,
-            '>' => Button::Right,
-            _ => panic!(),
-        })
-        .collect()
+    input.chars().map(parse_button).collect()
+}
+
+fn parse_button(ch: char) -> Button {
+    match ch {
+        '0'..='9' => Button::Num(ch.to_digit(10).unwrap() as u8),
+        'A' => Button::Activate,
+        'v' => Button::Down,
+        '^' => Button::Up,
+        '<' => Button::Left,
+        '>' => Button::Right,
+        ' ' | '#' => Button::Empty,
+        _ => Button::Key(ch),
+    }
+}
+
+// The presses at some level of the chain, made one move at a time from the optimal moves worked out
+// by move_cost, so even a long chain can be walked without holding the whole sequence
+struct Presses<'a> {
+    keypads: &'a [Keypad],
+    move_cache: &'a MoveCache,
+    level: usize,
+    // What each level above the one being walked still has to press, and where its arm is
+    stack: Vec<(&'a [Button], usize, Pos)>,
+}
+
+impl<'a> Presses<'a> {
+    // The human's presses that type the code
+    fn new(code: &'a [Button], keypads: &'a [Keypad], move_cache: &'a mut MoveCache) -> Self {
+        Self::at(code, keypads.len(), keypads, move_cache)
+    }
+
+    // What gets pressed on the keypad driving keypads[level - 1], or on the door for level 0, when the
+    // human types the code as fast as possible
+    fn at(
+        code: &'a [Button],
+        level: usize,
+        keypads: &'a [Keypad],
+        move_cache: &'a mut MoveCache,
+    ) -> Self {
+        expand_count(code, 0, keypads, move_cache);
+        Self {
+            keypads,
+            move_cache,
+            level,
+            stack: vec![(code, 0, keypads.first().map_or((0, 0), |k| k.pos))],
+        }
+    }
+}
+
+impl Iterator for Presses<'_> {
+    type Item = Button;
+
+    fn next(&mut self) -> Option<Button> {
+        loop {
+            let depth = self.stack.len().checked_sub(1)?;
+            let (buttons, i, pos) = self.stack.last_mut()?;
+            let Some(button) = buttons.get(*i).copied() else {
+                self.stack.pop();
+                continue;
+            };
+            *i += 1;
+            if depth == self.level {
+                return Some(button);
+            }
+
+            let keypad = &self.keypads[depth];
+            let goal = keypad.find(button).unwrap();
+            let (_, presses) = &self.move_cache[&(*pos, goal, depth)];
+            *pos = goal;
+            let next = self.keypads.get(depth + 1).map_or((0, 0), |k| k.pos);
+            self.stack.push((presses, 0, next));
+        }
+    }
+}
+
+// Squashes repeated presses into (button, times) runs
+fn run_lengths(presses: impl Iterator<Item = Button>) -> impl Iterator<Item = (Button, usize)> {
+    let mut presses = presses.peekable();
+    std::iter::from_fn(move || {
+        let button = presses.next()?;
+        let mut times = 1;
+        while presses.next_if_eq(&button).is_some() {
+            times += 1;
+        }
+        Some((button, times))
+    })
+}
+
+// Every level's presses, human at the top and door at the bottom, each press lined up under the human
+// press that made it happen. Only for short chains, as the rows are as long as the human sequence.
+fn side_by_side(code: &[Button], keypads: &[Keypad], move_cache: &mut MoveCache) -> String {
+    fn render(
+        buttons: &[Button],
+        depth: usize,
+        keypads: &[Keypad],
+        move_cache: &MoveCache,
+        rows: &mut [String],
+    ) {
+        let Some(keypad) = keypads.get(depth) else {
+            for button in buttons {
+                write!(rows[depth], "{button}").unwrap();
+            }
+            return;
+        };
+
+        let mut pos = keypad.pos;
+        for button in buttons {
+            let goal = keypad.find(*button).unwrap();
+            render(
+                &move_cache[&(pos, goal, depth)].1,
+                depth + 1,
+                keypads,
+                move_cache,
+                rows,
+            );
+            pos = goal;
+
+            let pad = rows[depth + 1].len() - rows[depth].len() - 1;
+            write!(rows[depth], "{}{button}", " ".repeat(pad)).unwrap();
+        }
+    }
+
+    expand_count(code, 0, keypads, move_cache);
+    let mut rows = vec![String::new(); keypads.len() + 1];
+    render(code, 0, keypads, move_cache, &mut rows);
+    rows.reverse();
+    rows.join("\n")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    OverGap,
+    OffKeypad,
+    // Only arrows move an arm, so anything else pressed on the keypad driving it is a mistake
+    NotAnArrow,
+}
+
+// An arm went somewhere it must not, on the given human press (counting from 0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ArmFault {
+    depth: usize,
+    time: usize,
+    fault: Fault,
+}
+
+impl Display for ArmFault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (time, depth) = (self.time, self.depth);
+        match self.fault {
+            Fault::OverGap => write!(
+                f,
+                "press {time} moves the arm over keypad {depth} over a gap"
+            ),
+            Fault::OffKeypad => write!(
+                f,
+                "press {time} moves the arm over keypad {depth} off its keypad"
+            ),
+            Fault::NotAnArrow => write!(
+                f,
+                "press {time} sends something other than an arrow to the arm over keypad {depth}"
+            ),
+        }
+    }
+}
+
+// Every arm in the chain, moved along one human press at a time
+#[derive(Debug, Clone)]
+struct Simulator<'a> {
+    keypads: &'a [Keypad],
+    arms: Vec<Pos>,
+    time: usize,
+}
+
+impl<'a> Simulator<'a> {
+    fn new(keypads: &'a [Keypad]) -> Self {
+        Self {
+            keypads,
+            arms: keypads.iter().map(|k| k.pos).collect(),
+            time: 0,
+        }
+    }
+
+    // Passes a human press down the chain, returning the button typed on the door if it gets that far
+    fn press(&mut self, press: Button) -> Result<Option<Button>, ArmFault> {
+        let time = self.time;
+        self.time += 1;
+
+        let mut button = press;
+        for depth in (0..self.keypads.len()).rev() {
+            let (keypad, arm) = (&self.keypads[depth], self.arms[depth]);
+            if button != Button::Activate {
+                self.arms[depth] = keypad
+                    .step_or_fault(arm, button)
+                    .map_err(|fault| ArmFault { depth, time, fault })?;
+                return Ok(None);
+            }
+            button = *keypad.grid.get(arm.0, arm.1).unwrap().val;
+        }
+
+        Ok(Some(button))
+    }
+}
+
+// Follows the presses through the chain, returning what gets typed on the door
+fn simulate(
+    keypads: &[Keypad],
+    presses: impl IntoIterator<Item = Button>,
+) -> Result<Vec<Button>, ArmFault> {
+    let mut simulator = Simulator::new(keypads);
+    let mut typed = Vec::new();
+    for press in presses {
+        typed.extend(simulator.press(press)?);
+    }
+    Ok(typed)
+}
+
+// The fewest human presses that type the code, found by searching over every arm's position at once.
+// Only usable for short chains, but it knows nothing about how move_cost splits up the problem.
+#[cfg(test)]
+fn fewest_presses(code: &[Button], keypads: &[Keypad]) -> Option<usize> {
+    use std::collections::{HashSet, VecDeque};
+
+    let start = Simulator::new(keypads);
+    let mut to_visit = VecDeque::from([(start.clone(), 0)]);
+    let mut visited = HashSet::from([(start.arms, 0)]);
+    while let Some((simulator, typed)) = to_visit.pop_front() {
+        for press in PRESSES {
+            let mut simulator = simulator.clone();
+            let typed = match simulator.press(press) {
+                Ok(None) => typed,
+                Ok(Some(button)) if code.get(typed) == Some(&button) => typed + 1,
+                _ => continue,
+            };
+
+            if typed == code.len() {
+                return Some(simulator.time);
+            }
+            if visited.insert((simulator.arms.clone(), typed)) {
+                to_visit.push_back((simulator, typed));
+            }
+        }
+    }
+
+    None
 }
 
 pub fn main() {
     let input = parse_input();
-    println!("Part 1: {}", part1_opt(&input));
-    println!("Part 2: {}", part2(&input));
+
+    // `cargo run --bin 21 -- <command>` does something other than solving:
+    // - `chain <layout file>...` types the codes through the keypads in the given files, starting
+    //   from the one the codes are typed on
+    // - `presses <code> [robots]` prints what the human presses to type the code, as runs of presses,
+    //   through the given number of robots on directional keypads (2 by default)
+    // - `show <code>` prints what everyone in the part 1 chain presses, lined up
+    // - `simulate <presses> [robots]` prints what the human presses end up typing on the door
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("chain") => {
+            let layouts = args
+                .map(|path| std::fs::read_to_string(path).unwrap())
+                .collect::<Vec<_>>();
+            let layouts = layouts.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+            let keypads = chain(&layouts).unwrap_or_else(|e| panic!("{e}"));
+            for keypad in &keypads {
+                println!("{keypad}");
+            }
+
+            let mut cache = HashMap::new();
+            let mut score = 0;
+            for code in &input {
+                let presses = expand_count(code, 0, &keypads, &mut cache);
+                println!(
+                    "{}: {presses}",
+                    code.iter().map(|b| b.to_string()).collect::<String>()
+                );
+                score += presses * val(code);
+            }
+            println!("Complexity: {score}");
+        }
+        Some("presses") => {
+            let code = parse_buttons(&args.next().expect("code"));
+            let robots = args.next().map_or(2, |n| n.parse().unwrap());
+            let keypads = keypads_n(robots);
+            let mut cache = HashMap::new();
+            for (button, times) in run_lengths(Presses::new(&code, &keypads, &mut cache)) {
+                println!("{button} x{times}");
+            }
+        }
+        Some("simulate") => {
+            let presses = parse_buttons(&args.next().expect("presses"));
+            let robots = args.next().map_or(2, |n| n.parse().unwrap());
+            match simulate(&keypads_n(robots), presses) {
+                Ok(code) => println!("{}", code.iter().map(|b| b.to_string()).collect::<String>()),
+                Err(fault) => println!("{fault}"),
+            }
+        }
+        Some("show") => {
+            let code = parse_buttons(&args.next().expect("code"));
+            println!("{}", side_by_side(&code, &keypads(), &mut HashMap::new()));
+        }
+        _ => {
+            println!("Part 1: {}", part1(&input));
+            println!("Part 2: {}", part2(&input));
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use super::*;
 
     #[test]
-    fn test_moves_between() {
-        let grid = numeric();
-        let start = (3, 2);
-        let goal_0 = find_button(&grid, Button::Num(0)).unwrap();
-        let avoid = find_button(&grid, Button::Empty).unwrap();
+    fn test_presses() {
+        let code = parse_buttons("029A");
+        let door = chain(&[NUMERIC]).unwrap();
+        let presses = Presses::new(&code, &door, &mut HashMap::new()).collect::<Vec<_>>();
+        assert_eq!(12, presses.len());
+        assert_eq!(Some(code.clone()), simulate(&door, presses).ok());
 
+        let keypads = keypads();
+        let mut cache = HashMap::new();
+        let presses = Presses::new(&code, &keypads, &mut cache).collect::<Vec<_>>();
+        assert_eq!(68, presses.len());
         assert_eq!(
-            ((0, Button::Down), (1, Button::Left)),
-            moves_between(start, goal_0, avoid)
+            Some(code.clone()),
+            simulate(&keypads, presses.iter().copied()).ok()
         );
 
-        let goal_2 = find_button(&grid, Button::Num(2)).unwrap();
+        // Every level typed out by the one above it
+        for level in 0..keypads.len() {
+            let above = Presses::at(&code, level + 1, &keypads, &mut cache);
+            let typed = simulate(&keypads[level..=level], above).ok();
+            let expected = Presses::at(&code, level, &keypads, &mut cache);
+            assert_eq!(Some(expected.collect()), typed);
+        }
+
+        let runs = run_lengths(presses.iter().copied()).collect::<Vec<_>>();
+        assert_eq!(presses.len(), runs.iter().map(|(_, n)| n).sum::<usize>());
+        assert!(runs.windows(2).all(|w| w[0].0 != w[1].0));
+    }
+
+    #[test]
+    fn test_long_chain() {
+        // Far too many presses to hold, but they can still be walked and run-length encoded
+        let code = parse_buttons("179A");
+        let keypads = keypads_n(25);
+        let mut cache = HashMap::new();
+        let total = expand_count(&code, 0, &keypads, &mut cache);
+        assert!(total > 1 << 32);
+
+        let runs = run_lengths(Presses::new(&code, &keypads, &mut cache))
+            .take(1000)
+            .collect::<Vec<_>>();
+        assert_eq!(1000, runs.len());
+    }
+
+    #[test]
+    fn test_side_by_side() {
+        let code = parse_buttons("029A");
+        let shown = side_by_side(&code, &keypads(), &mut HashMap::new());
+        let rows = shown.lines().collect::<Vec<_>>();
+        assert_eq!(4, rows.len());
+        assert_eq!(68, rows[0].len());
+        assert_eq!("029A", rows[3].split_whitespace().collect::<String>());
+
+        // Everything a robot presses is under an A from the level above
+        for pair in rows.windows(2) {
+            for (col, ch) in pair[1].char_indices().filter(|(_, ch)| *ch != ' ') {
+                assert_eq!(Some('A'), pair[0].chars().nth(col), "{ch} at {col}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_example() {
+        let codes = ["029A", "980A", "179A", "456A", "379A"].map(parse_buttons);
+        let mut cache = HashMap::new();
+        let lengths = codes
+            .iter()
+            .map(|c| expand_count(c, 0, &keypads(), &mut cache))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![68, 60, 68, 64, 64], lengths);
+        assert_eq!(126384, part1(&codes.to_vec()));
+
+        let mut cache = HashMap::new();
+        let keypads = keypads_n(25);
+        let complexity = codes
+            .iter()
+            .map(|c| expand_count(c, 0, &keypads, &mut cache) * val(c))
+            .sum::<usize>();
+        assert_eq!(154115708116294, complexity);
+    }
+
+    #[test]
+    fn test_layouts() {
+        let keypad = Keypad::parse(DIRECTIONAL).unwrap();
+        assert_eq!((0, 2), keypad.pos);
+        assert_eq!(vec![(0, 0)], keypad.gaps);
+        assert_eq!(" ^A\n<v>\n", keypad.to_string());
+
+        // Named buttons, ragged rows and more than one gap
+        let keypad = Keypad::parse("xyz\n#wA\nq").unwrap();
+        assert_eq!(Some((0, 0)), keypad.find(Button::Key('x')));
+        assert_eq!(Some((2, 0)), keypad.find(Button::Key('q')));
+        assert_eq!(vec![(1, 0), (2, 1), (2, 2)], keypad.gaps);
+
+        assert!(Keypad::parse("12\n3").is_err());
+        assert!(Keypad::parse("1A\nA2").is_err());
+        assert!(chain(&[NUMERIC, NUMERIC]).is_err());
+        assert!(chain(&[DIRECTIONAL, NUMERIC]).is_err());
+    }
+
+    #[test]
+    fn test_custom_chain() {
+        // Going from A to x has to go up first and coming back right first: ^<<A>>vA
+        let keypads = chain(&["xyz\n wA"]).unwrap();
+        let mut cache = HashMap::new();
         assert_eq!(
-            ((1, Button::Up), (0, Button::Right)),
-            moves_between(goal_0, goal_2, avoid)
+            8,
+            expand_count(&parse_buttons("xA"), 0, &keypads, &mut cache)
         );
 
-        let goal_2 = find_button(&grid, Button::Num(2)).unwrap();
+        let keypads = chain(&[NUMERIC, DIRECTIONAL]).unwrap();
+        let mut cache = HashMap::new();
         assert_eq!(
-            ((1, Button::Up), (0, Button::Right)),
-            moves_between(goal_0, goal_2, avoid)
+            "v<<A>>^A<A>AvA<^AA>A<vAAA>^A".len(),
+            expand_count(&parse_buttons("029A"), 0, &keypads, &mut cache)
         );
+    }
 
-        let goal_9 = find_button(&grid, Button::Num(9)).unwrap();
+    #[test]
+    fn test_gaps_in_the_way() {
+        // Neither going up first nor left first gets from A to 1 here: it takes ^<<^
+        let keypads = chain(&["1 3\n456\n7 A", DIRECTIONAL]).unwrap();
+        let code = parse_buttons("1A");
+        let mut cache = HashMap::new();
         assert_eq!(
-            ((2, Button::Up), (1, Button::Right)),
-            moves_between(goal_2, goal_9, avoid)
+            fewest_presses(&code, &keypads),
+            Some(expand_count(&code, 0, &keypads, &mut cache))
         );
 
+        // Nor does any way straight there, the arm has to go around the gaps
+        let keypads = chain(&["1 x\ny A\nzwv", DIRECTIONAL, DIRECTIONAL]).unwrap();
+        let code = parse_buttons("1vA");
+        let mut cache = HashMap::new();
         assert_eq!(
-            ((3, Button::Down), (0, Button::Right)),
-            moves_between(goal_9, start, avoid)
+            fewest_presses(&code, &keypads),
+            Some(expand_count(&code, 0, &keypads, &mut cache))
         );
     }
 
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn shuffle<T>(&mut self, items: &mut [T]) {
+            for i in (1..items.len()).rev() {
+                items.swap(i, self.next(i + 1));
+            }
+        }
+    }
+
+    // Lays the buttons out at random on a keypad of the given width, with a few gaps thrown in, and
+    // keeps trying until every button can be reached from every other
+    fn random_keypad(rng: &mut Rng, buttons: &str, width: usize) -> Keypad {
+        loop {
+            let mut cells = buttons.chars().collect::<Vec<_>>();
+            cells.extend(std::iter::repeat_n(' ', 1 + rng.next(3)));
+            rng.shuffle(&mut cells);
+            let layout = cells
+                .chunks(width)
+                .map(|row| row.iter().collect::<String>())
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            let keypad = Keypad::parse(&layout).unwrap();
+            let mut reached = HashSet::from([keypad.pos]);
+            let mut to_visit = vec![keypad.pos];
+            while let Some(pos) = to_visit.pop() {
+                for arrow in &PRESSES[..4] {
+                    if let Some(next) = keypad.step(pos, *arrow) {
+                        if reached.insert(next) {
+                            to_visit.push(next);
+                        }
+                    }
+                }
+            }
+            if reached.len() == keypad.buttons.len() {
+                return keypad;
+            }
+        }
+    }
+
     #[test]
-    fn test_expand() {
-        let mut keypad_a = Keypad {
-            grid: numeric(),
-            pos: (3, 2),
-        };
+    fn test_simulator() {
+        let presses =
+            parse_buttons("<vA<AA>>^AvAA<^A>A<v<A>>^AvA^A<vA>^A<v<A>^A>AAvA^A<v<A>A>^AAAvA<^A>A");
+        assert_eq!(Ok(parse_buttons("029A")), simulate(&keypads(), presses));
+
+        // The door arm walks from A onto 0 and then the gap
+        let door = chain(&[NUMERIC]).unwrap();
+        assert_eq!(
+            Err(ArmFault {
+                depth: 0,
+                time: 2,
+                fault: Fault::OverGap
+            }),
+            simulate(&door, parse_buttons("<A<"))
+        );
+        assert_eq!(
+            Err(ArmFault {
+                depth: 0,
+                time: 0,
+                fault: Fault::OffKeypad
+            }),
+            simulate(&door, parse_buttons(">"))
+        );
+
+        // The human moves the last robot's arm onto ^ and presses it, sending the middle arm off the
+        // top of its keypad
+        let fault = simulate(&keypads(), parse_buttons("<A")).unwrap_err();
+        assert_eq!(
+            ArmFault {
+                depth: 1,
+                time: 1,
+                fault: Fault::OffKeypad
+            },
+            fault
+        );
+        assert_eq!(
+            "press 1 moves the arm over keypad 1 off its keypad",
+            fault.to_string()
+        );
+
+        // Only arrows and A are any use to the human, and the arm over the last keypad doesn't budge
+        // for anything else
+        let fault = simulate(&keypads(), parse_buttons("0A")).unwrap_err();
+        assert_eq!(
+            ArmFault {
+                depth: 2,
+                time: 0,
+                fault: Fault::NotAnArrow
+            },
+            fault
+        );
+        assert_eq!(
+            "press 0 sends something other than an arrow to the arm over keypad 2",
+            fault.to_string()
+        );
+        assert_eq!(
+            Err(ArmFault {
+                depth: 0,
+                time: 4,
+                fault: Fault::NotAnArrow
+            }),
+            simulate(&door, parse_buttons("<A^A3"))
+        );
 
-        let target = expand(
-            vec![
-                Button::Num(0),
-                Button::Num(2),
-                Button::Num(9),
-                Button::Activate,
-            ],
-            &mut keypad_a,
+        // Pressing < on the last robot's keypad takes the middle arm from A to ^, and again to the gap
+        let fault = simulate(&keypads(), parse_buttons("v<<AA")).unwrap_err();
+        assert_eq!(
+            (1, 4, Fault::OverGap),
+            (fault.depth, fault.time, fault.fault)
         );
+    }
 
-        assert_eq!(parse_buttons("<A^A^^>AvvvA"), target);
+    #[test]
+    fn test_optimiser_against_simulator() {
+        let mut rng = Rng(0x2024_1225);
+        for _i in 0..40 {
+            let width = 3 + rng.next(2);
+            let mut keypads = vec![random_keypad(&mut rng, "0123456789A", width)];
+            for _j in 0..1 + rng.next(4) {
+                let width = 2 + rng.next(3);
+                keypads.push(random_keypad(&mut rng, "^v<>A", width));
+            }
+
+            let mut code = (0..4)
+                .map(|_| Button::Num(rng.next(10) as u8))
+                .collect::<Vec<_>>();
+            code.push(Button::Activate);
+
+            let mut cache = HashMap::new();
+            let expected = expand_count(&code, 0, &keypads, &mut cache);
+            let presses = Presses::new(&code, &keypads, &mut cache).collect::<Vec<_>>();
+            assert_eq!(expected, presses.len());
+            assert_eq!(Ok(code), simulate(&keypads, presses));
+        }
+    }
+
+    #[test]
+    fn test_against_brute_force() {
+        let mut rng = Rng(0x2024_1221);
+        for _i in 0..40 {
+            let door = random_keypad(&mut rng, "0123456A", 3);
+            let mut keypads = vec![door];
+            for _j in 0..1 + rng.next(2) {
+                let width = 2 + rng.next(2);
+                keypads.push(random_keypad(&mut rng, "^v<>A", width));
+            }
+
+            let mut code = (0..3)
+                .map(|_| Button::Num(rng.next(7) as u8))
+                .collect::<Vec<_>>();
+            code.push(Button::Activate);
+
+            let mut cache = HashMap::new();
+            let layouts = keypads.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+            assert_eq!(
+                fewest_presses(&code, &keypads),
+                Some(expand_count(&code, 0, &keypads, &mut cache)),
+                "typing {code:?} on {layouts:?}"
+            );
+        }
     }
 }
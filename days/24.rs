@@ -1,6 +1,7 @@
-// DISCLAIMER: this only works on my input, since I did a manual swap from graph analysis instead of coding it
-
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Write},
+};
 
 use regex::Regex;
 
@@ -10,46 +11,25 @@ struct Gate {
     a: String,
     b: String,
     out: String,
-    tag: Option<(T, usize, usize)>,
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum T {
-    Carry,
-    Intermediate,
-    Out,
-}
-
-impl Gate {
-    fn apply(&self, values: &mut Values) -> bool {
-        let Some(a) = values.get(&self.a) else {
-            return false;
-        };
-
-        let Some(b) = values.get(&self.b) else {
-            return false;
-        };
-
-        values.insert(
-            self.out.clone(),
-            match self.op {
-                Op::And => *a && *b,
-                Op::Or => *a || *b,
-                Op::Xor => *a != *b,
-            },
-        );
-
-        true
-    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 enum Op {
     And,
     Or,
     Xor,
 }
 
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::And => write!(f, "AND"),
+            Op::Or => write!(f, "OR"),
+            Op::Xor => write!(f, "XOR"),
+        }
+    }
+}
+
 type Values = HashMap<String, bool>;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -70,308 +50,672 @@ impl Logic {
     }
 }
 
-fn part1(mut logic: Logic) -> u64 {
-    let mut unresolved_gates = logic.gates.clone();
-    while !unresolved_gates.is_empty() {
-        unresolved_gates.retain(|gate| !gate.apply(&mut logic.values));
-    }
-
-    make_num(&logic.values, 'z')
-}
+// A wire loop, as the list of wires going around it
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Cycle(Vec<String>);
 
-fn run(logic: &mut Logic) {
-    let mut unresolved_gates = logic.gates.clone();
-    while !unresolved_gates.is_empty() {
-        unresolved_gates.retain(|gate| !gate.apply(&mut logic.values));
+impl Display for Cycle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cycle: {}", self.0.join(" -> "))?;
+        if let Some(first) = self.0.first() {
+            write!(f, " -> {first}")?;
+        }
+        Ok(())
     }
 }
 
-fn tag0(logic: &mut Logic) -> bool {
-    let mut tag_or = false;
-    let mut tag_and = false;
-    for g in logic.gates.iter_mut() {
-        if !tag_or && g.op == Op::Or && (g.a == "x00" && g.b == "y00")
-            || (g.a == "y00" && g.b == "x00")
-        {
-            g.tag = Some((T::Out, 0, 0));
-            tag_or = true;
-        }
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum CompileError {
+    Cycle(Cycle),
+    // A wire more than one gate drives
+    MultipleDrivers(String),
+}
 
-        if !tag_and && g.op == Op::And && (g.a == "x00" && g.b == "y00")
-            || (g.a == "y00" && g.b == "x00")
-        {
-            g.tag = Some((T::Carry, 0, 0));
-            tag_and = true;
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Cycle(cycle) => write!(f, "{cycle}"),
+            CompileError::MultipleDrivers(wire) => {
+                write!(f, "{wire} is driven by more than one gate")
+            }
         }
     }
-
-    tag_or && tag_and
 }
 
-#[derive(Debug, PartialEq, Eq)]
-enum Out {
-    Found,
-    Swapped(String, String),
-    Failed,
+// The gates compiled down to wire ids, in an order where every gate comes after the gates driving its
+// inputs, so evaluating is a single pass. Each wire holds 64 lanes, so 64 inputs can be run at once.
+#[derive(Debug, Clone)]
+struct Netlist {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+    gates: Vec<(Op, usize, usize, usize)>,
+    // For each wire, the gate driving it and the gates reading it, as indices into `gates`
+    driver: Vec<Option<usize>>,
+    readers: Vec<Vec<usize>>,
+    x: Vec<usize>,
+    y: Vec<usize>,
+    z: Vec<usize>,
 }
 
-fn tagn(logic: &mut Logic, n: usize) -> Out {
-    let xn = format!("x{n:02}");
-    let yn = format!("y{n:02}");
-    let zn = format!("z{n:02}");
+impl Netlist {
+    fn compile(logic: &Logic) -> Result<Self, CompileError> {
+        let mut names: Vec<String> = Vec::new();
+        let mut ids: HashMap<String, usize> = HashMap::new();
+        let mut intern = |name: &str| {
+            *ids.entry(name.to_string()).or_insert_with(|| {
+                names.push(name.to_string());
+                names.len() - 1
+            })
+        };
 
-    let Some(prev_carry) = logic
-        .gates
-        .iter()
-        .find(|g| g.tag == Some((T::Carry, n - 1, 0)))
-        .map(|g| g.out.clone())
-    else {
-        panic!("No previous carry for {n}");
-    };
+        for wire in logic.values.keys() {
+            intern(wire);
+        }
+        let gates = logic
+            .gates
+            .iter()
+            .map(|g| (g.op, intern(&g.a), intern(&g.b), intern(&g.out)))
+            .collect::<Vec<_>>();
+
+        // Kahn's algorithm: wires that no gate drives are available from the start
+        let mut driver = vec![None; names.len()];
+        for (i, g) in gates.iter().enumerate() {
+            if driver[g.3].replace(i).is_some() {
+                return Err(CompileError::MultipleDrivers(names[g.3].clone()));
+            }
+        }
+        let mut readers = vec![Vec::new(); names.len()];
+        let mut pending = vec![0; gates.len()];
+        for (i, g) in gates.iter().enumerate() {
+            for input in [g.1, g.2] {
+                if driver[input].is_some() {
+                    readers[input].push(i);
+                    pending[i] += 1;
+                }
+            }
+        }
+
+        let mut ready = (0..gates.len())
+            .filter(|i| pending[*i] == 0)
+            .collect::<Vec<_>>();
+        let mut order = Vec::with_capacity(gates.len());
+        while let Some(i) = ready.pop() {
+            order.push(gates[i]);
+            for r in readers[gates[i].3].iter() {
+                pending[*r] -= 1;
+                if pending[*r] == 0 {
+                    ready.push(*r);
+                }
+            }
+        }
 
-    println!("Previous carry is {}", prev_carry);
+        if order.len() < gates.len() {
+            // Every gate left over waits on another left over gate, so following the inputs has to loop
+            let mut seen = vec![None; names.len()];
+            let mut path = Vec::new();
+            let mut gate = (0..gates.len()).find(|i| pending[*i] > 0).unwrap();
+            loop {
+                let out = gates[gate].3;
+                if let Some(start) = seen[out] {
+                    let wires = path[start..]
+                        .iter()
+                        .rev()
+                        .map(|w: &usize| names[*w].clone())
+                        .collect();
+                    return Err(CompileError::Cycle(Cycle(wires)));
+                }
+                seen[out] = Some(path.len());
+                path.push(out);
 
-    // Given that we have tagged the previous carry, try to find the following gates:
-    let mut a = None;
-    // an intermediate that xors the 2 basic values
-    // i01x = x01 XOR y01
-    for g in logic.gates.iter_mut() {
-        if let Some(ref t) = g.tag {
-            if *t == (T::Intermediate, n, 0) {
-                a = Some(g.out.clone());
-                break;
+                let (_, a, b, _) = gates[gate];
+                gate = [a, b]
+                    .into_iter()
+                    .filter_map(|w| driver[w])
+                    .find(|g| pending[*g] > 0)
+                    .unwrap();
             }
-            continue;
         }
-        if g.op == Op::Xor && ((g.a == xn && g.b == yn) || (g.a == yn && g.b == xn)) {
-            g.tag = Some((T::Intermediate, n, 0));
-            a = Some(g.out.clone());
-            break;
+
+        let bus = |prefix: char| {
+            let mut wires = names
+                .iter()
+                .enumerate()
+                .filter_map(|(id, name)| {
+                    let bit = name.strip_prefix(prefix)?.parse::<usize>().ok()?;
+                    Some((bit, id))
+                })
+                .collect::<Vec<_>>();
+            wires.sort();
+            wires.into_iter().map(|(_, id)| id).collect::<Vec<_>>()
+        };
+        let (x, y, z) = (bus('x'), bus('y'), bus('z'));
+
+        let mut driver = vec![None; names.len()];
+        let mut readers = vec![Vec::new(); names.len()];
+        for (i, (_, a, b, out)) in order.iter().enumerate() {
+            driver[*out] = Some(i);
+            readers[*a].push(i);
+            if b != a {
+                readers[*b].push(i);
+            }
         }
+
+        Ok(Self {
+            names,
+            ids,
+            gates: order,
+            driver,
+            readers,
+            x,
+            y,
+            z,
+        })
     }
-    if a.is_none() {
-        panic!("Could not find a XOR intermediate for {n}");
-    }
-    println!("a: {:?}", a);
 
-    let mut b = None;
-    // an intermediate that ands the previous carry with the intermediate
-    // i01a = y01 AND x01
-    for g in logic.gates.iter_mut() {
-        if let Some(ref t) = g.tag {
-            if *t == (T::Intermediate, n, 1) {
-                b = Some(g.out.clone());
-                break;
+    fn load(&self, values: &Values) -> Vec<u64> {
+        let mut wires = vec![0; self.names.len()];
+        for (name, v) in values.iter() {
+            if *v {
+                wires[self.ids[name]] = u64::MAX;
             }
-            continue;
         }
-        // if g.out.starts_with('z') {
-        //     continue;
-        // }
-        if g.op == Op::And && ((g.a == xn && g.b == yn) || (g.a == yn && g.b == xn)) {
-            g.tag = Some((T::Intermediate, n, 1));
-            b = Some(g.out.clone());
-            break;
+        wires
+    }
+
+    fn run(&self, wires: &mut [u64]) {
+        for (op, a, b, out) in self.gates.iter() {
+            wires[*out] = match op {
+                Op::And => wires[*a] & wires[*b],
+                Op::Or => wires[*a] | wires[*b],
+                Op::Xor => wires[*a] ^ wires[*b],
+            };
         }
     }
-    if b.is_none() {
-        panic!("Could not find intermediate {xn} AND {yn}");
+
+    fn read(&self, wires: &[u64], bus: &[usize], lane: usize) -> u128 {
+        bus.iter()
+            .rev()
+            .fold(0, |n, w| (n << 1) | ((wires[*w] >> lane) & 1) as u128)
     }
-    println!("b: {:?}", b);
 
-    // z01 = (i01x XOR c00)
-    let mut d = None;
-    for g in logic.gates.iter_mut() {
-        if g.op == Op::Xor
-            && g.out == zn
-            && ((g.a == prev_carry && &g.b == a.as_ref().unwrap())
-                || (&g.a == a.as_ref().unwrap() && g.b == prev_carry))
-        {
-            g.tag = Some((T::Out, n, 0));
-            d = Some(g.out.clone());
-            break;
-        }
+    fn id(&self, wire: &str) -> Option<usize> {
+        self.ids.get(wire).copied()
     }
-    if d.is_none() {
-        for g in logic.gates.iter_mut() {
-            if g.tag.is_none()
-                && g.op == Op::Xor
-                && ((g.a == prev_carry && &g.b == a.as_ref().unwrap())
-                    || (&g.a == a.as_ref().unwrap() && g.b == prev_carry))
-            {
-                g.tag = Some((T::Out, n, 0));
-                d = Some(g.out.clone());
-                break;
+
+    fn names(&self, wires: &[usize]) -> Vec<String> {
+        wires.iter().map(|w| self.names[*w].clone()).collect()
+    }
+
+    // The wires feeding straight into `wire`
+    fn fan_in(&self, wire: usize) -> Vec<usize> {
+        self.driver[wire]
+            .map(|g| vec![self.gates[g].1, self.gates[g].2])
+            .unwrap_or_default()
+    }
+
+    // The wires `wire` feeds straight into
+    fn fan_out(&self, wire: usize) -> Vec<usize> {
+        self.readers[wire]
+            .iter()
+            .map(|g| self.gates[*g].3)
+            .collect()
+    }
+
+    fn reach(&self, wire: usize, next: impl Fn(usize) -> Vec<usize>) -> Vec<usize> {
+        let mut seen = vec![false; self.names.len()];
+        let mut to_visit = vec![wire];
+        while let Some(w) = to_visit.pop() {
+            if !seen[w] {
+                seen[w] = true;
+                to_visit.extend(next(w));
             }
         }
-        if let Some(d) = d {
-            println!("SWAPPED {zn} with {:?}", d);
-            logic.swap(&zn, &d);
 
-            return Out::Swapped(zn, d);
-        } else {
-            // Since we can't find a simple
-        }
+        (0..seen.len()).filter(|w| seen[*w]).collect()
     }
-    println!("d: {:?}", d);
 
-    let mut c = None;
-    // an intermediate that ands the previous carry and the xor intermediate
-    // i01b = c00 AND i01x
-    for g in logic.gates.iter_mut() {
-        if let Some(ref t) = g.tag {
-            if *t == (T::Intermediate, n, 2) {
-                c = Some(g.out.clone());
-                break;
+    // Every wire `wire` depends on, itself included
+    fn cone(&self, wire: usize) -> Vec<usize> {
+        self.reach(wire, |w| self.fan_in(w))
+    }
+
+    // The z outputs that change with `wire`, in bit order
+    fn affected_outputs(&self, wire: usize) -> Vec<usize> {
+        let reached = self.reach(wire, |w| self.fan_out(w));
+        self.z
+            .iter()
+            .copied()
+            .filter(|z| reached.binary_search(z).is_ok())
+            .collect()
+    }
+
+    // Compares two wires as functions of the inputs they depend on, trying every assignment when there
+    // are few enough inputs and `rounds` batches of 64 random ones otherwise
+    fn equivalent(&self, a: usize, b: usize, rounds: usize, mut seed: u64) -> Equivalence {
+        let mut inputs = self.cone(a);
+        inputs.extend(self.cone(b));
+        inputs.retain(|w| self.driver[*w].is_none());
+        inputs.sort();
+        inputs.dedup();
+
+        let exhaustive = inputs.len() <= EXHAUSTIVE_INPUTS;
+        let total = if exhaustive {
+            1 << inputs.len()
+        } else {
+            rounds * 64
+        };
+
+        let mut wires = vec![0; self.names.len()];
+        for start in (0..total).step_by(64) {
+            let lanes = (total - start).min(64);
+            for (i, w) in inputs.iter().enumerate() {
+                wires[*w] = if exhaustive {
+                    (0..lanes).fold(0, |v, lane| {
+                        v | ((((start + lane) >> i) & 1) as u64) << lane
+                    })
+                } else {
+                    next_random(&mut seed)
+                };
+            }
+            self.run(&mut wires);
+
+            let mask = u64::MAX >> (64 - lanes);
+            let diff = (wires[a] ^ wires[b]) & mask;
+            if diff != 0 {
+                let lane = diff.trailing_zeros();
+                let assignment = inputs
+                    .iter()
+                    .map(|w| (self.names[*w].clone(), (wires[*w] >> lane) & 1 == 1))
+                    .collect();
+                return Equivalence::Differs(assignment);
             }
-            continue;
         }
-        if g.out != zn
-            && g.op == Op::And
-            && ((g.a == prev_carry && &g.b == a.as_ref().unwrap())
-                || (&g.a == a.as_ref().unwrap() && g.b == prev_carry))
-        {
-            g.tag = Some((T::Intermediate, n, 2));
-            c = Some(g.out.clone());
-            break;
+
+        if exhaustive {
+            Equivalence::Proven
+        } else {
+            Equivalence::Likely
         }
     }
-    if c.is_none() {
-        for g in logic.gates.iter_mut() {
-            if let Some(ref _t) = g.tag {
-                continue;
+
+    // Runs up to 64 additions in one pass, one per lane
+    fn add(&self, operands: &[(u64, u64)]) -> Vec<u128> {
+        assert!(operands.len() <= 64);
+        assert!(
+            self.x.len() <= 64 && self.y.len() <= 64,
+            "can't add with {}-bit and {}-bit inputs, 64 bits at most",
+            self.x.len(),
+            self.y.len()
+        );
+
+        let mut wires = vec![0; self.names.len()];
+        for (lane, (x, y)) in operands.iter().enumerate() {
+            for (bit, w) in self.x.iter().enumerate() {
+                wires[*w] |= ((x >> bit) & 1) << lane;
             }
-            if g.op == Op::And
-                && ((g.a == prev_carry && &g.b == a.as_ref().unwrap())
-                    || (&g.a == a.as_ref().unwrap() && g.b == prev_carry))
-            {
-                g.tag = Some((T::Intermediate, n, 2));
-                c = Some(g.out.clone());
-                println!("FOUND {g:?} to swap output");
-                break;
+            for (bit, w) in self.y.iter().enumerate() {
+                wires[*w] |= ((y >> bit) & 1) << lane;
             }
         }
 
-        return Out::Failed;
+        self.run(&mut wires);
+
+        (0..operands.len())
+            .map(|lane| self.read(&wires, &self.z, lane))
+            .collect()
     }
-    println!("c: {:?}", c);
+}
+
+// Up to this many inputs, `Netlist::equivalent` tries all of their values
+const EXHAUSTIVE_INPUTS: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Equivalence {
+    Proven,
+    // No difference on the random inputs tried
+    Likely,
+    // An assignment of the inputs where the two wires differ
+    Differs(Vec<(String, bool)>),
+}
+
+// xorshift, good enough to throw random operands at the circuit
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// Checks `rounds` batches of 64 random additions against the real thing
+fn adds_correctly(netlist: &Netlist, rounds: usize, mut seed: u64) -> bool {
+    let mask = u64::MAX
+        .checked_shr(64u32.saturating_sub(netlist.x.len() as u32))
+        .unwrap_or(0);
+    (0..rounds).all(|_| {
+        let operands = (0..64)
+            .map(|_| (next_random(&mut seed) & mask, next_random(&mut seed) & mask))
+            .collect::<Vec<_>>();
+
+        netlist
+            .add(&operands)
+            .into_iter()
+            .zip(operands.iter())
+            .all(|(z, (x, y))| z == *x as u128 + *y as u128)
+    })
+}
 
-    // c01 = i01a OR i01b
-    let mut e = None;
-    for g in logic.gates.iter_mut() {
-        if !g.out.starts_with('z')
-            && g.op == Op::Or
-            && ((&g.a == b.as_ref().unwrap() && &g.b == c.as_ref().unwrap())
-                || (&g.a == c.as_ref().unwrap() && &g.b == b.as_ref().unwrap()))
-        {
-            g.tag = Some((T::Carry, n, 0));
-            e = Some(g.out.clone());
-            println!("Carry {n} is {}", g.out);
-            break;
+fn part1(logic: Logic) -> u128 {
+    let netlist = Netlist::compile(&logic).unwrap_or_else(|e| panic!("{e}"));
+    let mut wires = netlist.load(&logic.values);
+    netlist.run(&mut wires);
+
+    netlist.read(&wires, &netlist.z, 0)
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum FaultKind {
+    MissingGate { op: Op, a: String, b: String },
+    WrongOutput { expected: String, found: String },
+}
+
+// A place where the circuit stops looking like a ripple-carry adder.
+// `carry` is the carry coming into `bit`, if it could be found.
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Fault {
+    bit: usize,
+    carry: Option<String>,
+    kind: FaultKind,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bit {}: ", self.bit)?;
+        match &self.kind {
+            FaultKind::MissingGate { op, a, b } => write!(f, "no {op} gate combines {a} and {b}"),
+            FaultKind::WrongOutput { expected, found } => {
+                write!(f, "expected the result on {expected}, found it on {found}")
+            }
         }
     }
-    if e.is_none() {
-        panic!("Could not find a gate setting carry for {zn} from intermediates {b:?} and {c:?}");
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct Swap {
+    a: String,
+    b: String,
+    reason: Fault,
+}
+
+impl Display for Swap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} <-> {} ({})", self.a, self.b, self.reason)
     }
+}
 
-    Out::Found
+// Gates keyed by operation and inputs, with the inputs sorted so the lookup doesn't care about order
+struct GateIndex<'a> {
+    outputs: HashMap<(Op, &'a str, &'a str), &'a str>,
 }
 
-fn part2(mut logic: Logic) -> String {
-    let x = make_num(&logic.values, 'x');
-    let y = make_num(&logic.values, 'y');
+impl<'a> GateIndex<'a> {
+    fn new(logic: &'a Logic) -> Self {
+        let outputs = logic
+            .gates
+            .iter()
+            .map(|g| {
+                let (a, b) = sorted(&g.a, &g.b);
+                ((g.op, a, b), g.out.as_str())
+            })
+            .collect();
+
+        Self { outputs }
+    }
 
-    let expected = to_gates(x + y, 'z', 46);
-    run(&mut logic);
+    fn find(
+        &self,
+        op: Op,
+        a: &str,
+        b: &str,
+        bit: usize,
+        carry: Option<&str>,
+    ) -> Result<&'a str, Fault> {
+        let (sa, sb) = sorted(a, b);
+        self.outputs
+            .get(&(op, sa, sb))
+            .copied()
+            .ok_or_else(|| Fault {
+                bit,
+                carry: carry.map(str::to_string),
+                kind: FaultKind::MissingGate {
+                    op,
+                    a: a.to_string(),
+                    b: b.to_string(),
+                },
+            })
+    }
 
-    let wrong = expected
-        .into_iter()
-        .filter(|(gate, expected)| logic.values.get(gate).cloned().unwrap_or_default() != *expected)
-        .collect::<Vec<_>>();
+    // The other input of a gate with `op` that reads `wire`
+    fn partner(&self, op: Op, wire: &str) -> Option<&'a str> {
+        self.outputs
+            .keys()
+            .filter(|(o, _, _)| *o == op)
+            .find_map(|&(_, a, b)| match (a == wire, b == wire) {
+                (true, _) => Some(b),
+                (_, true) => Some(a),
+                _ => None,
+            })
+    }
+}
 
-    let simple = wrong
+fn sorted<'a>(a: &'a str, b: &'a str) -> (&'a str, &'a str) {
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn input_bits(logic: &Logic) -> usize {
+    ports(logic)
+        .0
         .iter()
-        .filter(|(g, _)| {
-            let Some(gate) = logic.gates.iter().find(|gg| gg.out == *g) else {
-                return false;
-            };
+        .filter(|w| matches!(bus_bit(w), Some(('x', _))))
+        .count()
+}
 
-            (gate.a.starts_with('x') || gate.a.starts_with('y'))
-                && (gate.b.starts_with('x') || gate.b.starts_with('y'))
-        })
-        .collect::<Vec<_>>();
+fn wrong_output(bit: usize, carry: Option<&str>, expected: String, found: &str) -> Fault {
+    Fault {
+        bit,
+        carry: carry.map(str::to_string),
+        kind: FaultKind::WrongOutput {
+            expected,
+            found: found.to_string(),
+        },
+    }
+}
+
+// Checks one bit against the ripple-carry adder structure, given the carry coming into it, and
+// returns the carry going out:
+// z00 = x00 XOR y00
+// c00 = x00 AND y00
+// zn  = (xn XOR yn) XOR cn-1
+// cn  = (xn AND yn) OR ((xn XOR yn) AND cn-1)
+fn check_bit<'a>(
+    index: &GateIndex<'a>,
+    n: usize,
+    carry: Option<&'a str>,
+) -> Result<&'a str, Fault> {
+    let xn = format!("x{n:02}");
+    let yn = format!("y{n:02}");
+    let zn = format!("z{n:02}");
+
+    let half_sum = index.find(Op::Xor, &xn, &yn, n, carry)?;
+    let half_carry = index.find(Op::And, &xn, &yn, n, carry)?;
+
+    let Some(c) = carry else {
+        if half_sum != zn {
+            return Err(wrong_output(n, carry, zn, half_sum));
+        }
+        return Ok(half_carry);
+    };
 
-    println!("wrong bits: {:?}", wrong.len());
-    println!(
-        "bits from simple gates: {:?}: {:?}",
-        simple,
-        logic.gates.iter().find(|g| g.out == simple[0].0)
-    );
-    for (w, _) in wrong.iter() {
-        println!("{:?}", candidates(&logic, w));
+    let sum = index.find(Op::Xor, half_sum, c, n, carry)?;
+    if sum != zn {
+        return Err(wrong_output(n, carry, zn, sum));
     }
 
-    if !tag0(&mut logic) {
-        panic!("Could not tag 0");
+    let carried = index.find(Op::And, half_sum, c, n, carry)?;
+    index.find(Op::Or, half_carry, carried, n, carry)
+}
+
+// Every bit that doesn't look like part of a ripple-carry adder, and whether the last carry is the
+// top z bit. After a faulty bit the carry is picked up again from the XOR that sums the next bit,
+// and if even that can't be found, the bits after it aren't checked.
+fn faults(logic: &Logic) -> Vec<Fault> {
+    let bits = input_bits(logic);
+    let index = GateIndex::new(logic);
+
+    let mut faults = Vec::new();
+    let mut carry: Option<&str> = None;
+    for n in 0..bits {
+        match check_bit(&index, n, carry) {
+            Ok(c) => carry = Some(c),
+            Err(fault) => {
+                faults.push(fault);
+                if n + 1 == bits {
+                    return faults;
+                }
+                let (x, y) = (format!("x{:02}", n + 1), format!("y{:02}", n + 1));
+                carry = index
+                    .find(Op::Xor, &x, &y, n + 1, None)
+                    .ok()
+                    .and_then(|half_sum| index.partner(Op::Xor, half_sum));
+                if carry.is_none() {
+                    return faults;
+                }
+            }
+        }
     }
-    logic.swap("hth", "tqr");
-    let mut swaps = vec!["hth".to_string(), "tqr".to_string()];
-    'outer: for i in 1..46 {
-        while let Out::Swapped(a, b) = tagn(&mut logic, i) {
-            swaps.push(a);
-            swaps.push(b);
-            if swaps.len() == 8 {
-                break 'outer;
+
+    let last = format!("z{bits:02}");
+    if let Some(c) = carry.filter(|c| *c != last) {
+        faults.push(wrong_output(bits, carry, last, c));
+    }
+    faults
+}
+
+// The first fault, if the circuit is an adder up to that point
+fn verify(logic: &Logic) -> Result<(), Fault> {
+    match faults(logic).into_iter().next() {
+        Some(fault) => Err(fault),
+        None => Ok(()),
+    }
+}
+
+// The gate outputs a fault at this bit could be blamed on: everything within two gates of the bit's
+// inputs and incoming carry, and whatever currently drives its z wire.
+fn suspects(logic: &Logic, fault: &Fault) -> Vec<String> {
+    let mut sources = vec![format!("x{:02}", fault.bit), format!("y{:02}", fault.bit)];
+    if let Some(ref c) = fault.carry {
+        sources.push(c.clone());
+    }
+
+    let mut res: Vec<String> = Vec::new();
+    for _depth in 0..2 {
+        let mut next = Vec::new();
+        for g in logic.gates.iter() {
+            if (sources.contains(&g.a) || sources.contains(&g.b)) && !res.contains(&g.out) {
+                res.push(g.out.clone());
+                next.push(g.out.clone());
             }
         }
+        sources = next;
+    }
+
+    let zn = format!("z{:02}", fault.bit);
+    if !res.contains(&zn) && logic.gates.iter().any(|g| g.out == zn) {
+        res.push(zn);
+    }
+    if let Some(ref c) = fault.carry {
+        if !res.contains(c) {
+            res.push(c.clone());
+        }
     }
 
-    swaps.sort();
-    swaps.join(",")
+    res
 }
 
-fn candidates(logic: &Logic, out: &str) -> Vec<String> {
-    let mut to_visit = vec![out.to_string()];
-    let mut cc = Vec::new();
+// Finds the smallest set of output swaps, up to `max_swaps`, that turns the circuit into a correct adder.
+fn repair(logic: &Logic, max_swaps: usize) -> Option<Vec<Swap>> {
+    (0..=max_swaps).find_map(|budget| repair_within(logic.clone(), budget))
+}
 
-    while let Some(n) = to_visit.pop() {
-        let Some(gate) = logic.gates.iter().find(|g| g.out == n) else {
-            continue;
-        };
-        cc.push(n.to_string());
+fn repair_within(logic: Logic, budget: usize) -> Option<Vec<Swap>> {
+    let Err(fault) = verify(&logic) else {
+        return Some(Vec::new());
+    };
+
+    if budget == 0 {
+        return None;
+    }
+
+    let suspects = suspects(&logic, &fault);
+    for (i, a) in suspects.iter().enumerate() {
+        for b in suspects[i + 1..].iter() {
+            let mut swapped = logic.clone();
+            swapped.swap(a, b);
+
+            // Only keep swaps that get us past the faulty bit
+            if verify(&swapped).is_err_and(|f| f.bit <= fault.bit) {
+                continue;
+            }
 
-        to_visit.push(gate.a.clone());
-        to_visit.push(gate.b.clone());
+            if let Some(mut rest) = repair_within(swapped, budget - 1) {
+                rest.insert(
+                    0,
+                    Swap {
+                        a: a.clone(),
+                        b: b.clone(),
+                        reason: fault.clone(),
+                    },
+                );
+                return Some(rest);
+            }
+        }
     }
 
-    cc
+    None
 }
 
-fn to_gates(num: u64, prefix: char, bits: usize) -> Vec<(String, bool)> {
-    let mut res = Vec::with_capacity(bits);
-    for i in 0..bits {
-        res.push((format!("{prefix}{i:#02}"), num & (1 << i) != 0));
+// The circuit with the swaps found by `repair` applied
+fn repaired(logic: &Logic, max_swaps: usize) -> Option<Logic> {
+    let mut fixed = logic.clone();
+    for swap in repair(logic, max_swaps)? {
+        fixed.swap(&swap.a, &swap.b);
     }
 
-    res
+    Some(fixed)
 }
 
-fn make_num(values: &Values, filter: char) -> u64 {
-    let mut zs = values
-        .iter()
-        .filter(|(k, _v)| k.starts_with(filter))
-        .collect::<Vec<_>>();
+fn part2(logic: Logic) -> String {
+    let Some(swaps) = repair(&logic, 4) else {
+        panic!("Could not turn the circuit into an adder with at most 4 swaps");
+    };
 
-    zs.sort();
-    zs.reverse();
+    let mut fixed = logic.clone();
+    for swap in swaps.iter() {
+        fixed.swap(&swap.a, &swap.b);
+    }
 
-    zs.iter().fold(0u64, |s, (_, b)| {
-        let mut s = s << 1;
-        if **b {
-            s += 1;
-        }
-        s
-    })
+    let netlist = Netlist::compile(&fixed).unwrap_or_else(|e| panic!("{e}"));
+    assert!(adds_correctly(&netlist, 16, 0x2024));
+
+    let mut wires = swaps
+        .into_iter()
+        .flat_map(|s| [s.a, s.b])
+        .collect::<Vec<_>>();
+    wires.sort();
+    wires.join(",")
 }
 
 fn parse_input() -> Logic {
@@ -396,7 +740,6 @@ fn parse_input() -> Logic {
             a: caps[1].to_string(),
             b: caps[6].to_string(),
             out: caps[7].to_string(),
-            tag: None,
         });
     }
 
@@ -405,21 +748,893 @@ fn parse_input() -> Logic {
 
 pub fn main() {
     let input = parse_input();
-    graph(&input);
-    println!("Part 1 : {}", part1(input.clone()));
-    println!("Part 2 : {}", part2(input.clone()));
-}
-
-#[allow(dead_code)]
-fn graph(logic: &Logic) {
-    for gate in logic.gates.iter() {
-        let arrowhead = match gate.op {
-            Op::And => "box",
-            Op::Or => "normal",
-            Op::Xor => "diamond",
+
+    // `cargo run --bin 24 -- <command>` does something other than solving:
+    // - `dot [bit]` prints the circuit for Graphviz, with the wires the repair would swap and the cone
+    //   of the given z bit highlighted
+    // - `verilog [repaired]` / `blif [repaired]` print the circuit, or the repaired one, for EDA tools
+    // - `repair` lists every bit that isn't wired as an adder, and the swaps that fix them
+    // - `check <file>` imports a .v or .blif netlist and checks whether it is an adder
+    // - `cone <wire>` lists what the wire depends on and which outputs it affects
+    // - `equiv <wire> <wire>` checks whether two wires compute the same thing
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("dot") => {
+            let mut highlight = Highlight::default();
+            for swap in repair(&input, 4).unwrap_or_default() {
+                highlight.suspicious.extend([swap.a, swap.b]);
+            }
+            if let Some(bit) = args.next() {
+                let bit = bit.parse::<usize>().expect("bit index");
+                let netlist = Netlist::compile(&input).unwrap_or_else(|e| panic!("{e}"));
+                let zn = netlist.id(&format!("z{bit:02}")).expect("output bit");
+                highlight.cone = netlist.names(&netlist.cone(zn)).into_iter().collect();
+            }
+            print!("{}", dot(&input, &highlight));
+        }
+        Some(format @ ("verilog" | "blif")) => {
+            let logic = if args.next().as_deref() == Some("repaired") {
+                repaired(&input, 4).expect("repairable circuit")
+            } else {
+                input
+            };
+            if format == "verilog" {
+                print!("{}", to_verilog(&logic));
+            } else {
+                print!("{}", to_blif(&logic));
+            }
+        }
+        Some("repair") => {
+            for fault in faults(&input) {
+                println!("{fault}");
+            }
+            for swap in repair(&input, 4).expect("repairable circuit") {
+                println!("swap {swap}");
+            }
+        }
+        Some("check") => {
+            let path = args.next().expect("netlist file");
+            let source = std::fs::read_to_string(&path).unwrap();
+            let logic = if path.ends_with(".blif") {
+                from_blif(&source)
+            } else {
+                from_verilog(&source)
+            }
+            .unwrap_or_else(|e| panic!("{path}: {e}"));
+
+            let faults = faults(&logic);
+            if faults.is_empty() {
+                println!("{path} is a {}-bit adder", input_bits(&logic));
+            } else {
+                println!("{path} is not an adder:");
+                for fault in faults {
+                    println!("{fault}");
+                }
+                for swap in repair(&logic, 4).unwrap_or_default() {
+                    println!("swap {swap}");
+                }
+            }
+        }
+        Some(command @ ("cone" | "equiv")) => {
+            let netlist = Netlist::compile(&input).unwrap_or_else(|e| panic!("{e}"));
+            let mut wire = || {
+                let name = args.next().expect("wire name");
+                netlist
+                    .id(&name)
+                    .unwrap_or_else(|| panic!("no wire named {name}"))
+            };
+
+            if command == "cone" {
+                let w = wire();
+                println!("fan-in: {}", netlist.names(&netlist.fan_in(w)).join(" "));
+                println!("fan-out: {}", netlist.names(&netlist.fan_out(w)).join(" "));
+                println!("cone: {}", netlist.names(&netlist.cone(w)).join(" "));
+                println!(
+                    "affects: {}",
+                    netlist.names(&netlist.affected_outputs(w)).join(" ")
+                );
+            } else {
+                let (a, b) = (wire(), wire());
+                println!("{:?}", netlist.equivalent(a, b, 64, 0x2024));
+            }
+        }
+        _ => {
+            println!("Part 1 : {}", part1(input.clone()));
+            println!("Part 2 : {}", part2(input.clone()));
+        }
+    }
+}
+
+// Wires to draw attention to in the DOT output
+#[derive(Debug, Default)]
+struct Highlight {
+    suspicious: HashSet<String>,
+    cone: HashSet<String>,
+}
+
+// The bus and bit of a numbered wire, like ('x', 7) for x07
+fn bus_bit(wire: &str) -> Option<(char, usize)> {
+    let mut chars = wire.chars();
+    let bus = chars.next()?;
+    let digits = chars.as_str();
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((bus, digits.parse().ok()?))
+}
+
+// The wires no gate drives are the inputs and the numbered z wires the outputs, everything else is
+// internal. Numbered wires come first, in bit order.
+fn ports(logic: &Logic) -> (Vec<String>, Vec<String>, Vec<String>) {
+    let driven = logic
+        .gates
+        .iter()
+        .map(|g| g.out.as_str())
+        .collect::<HashSet<_>>();
+    let mut wires = logic.values.keys().cloned().collect::<HashSet<_>>();
+    for g in logic.gates.iter() {
+        wires.extend([g.a.clone(), g.b.clone(), g.out.clone()]);
+    }
+    let mut wires = wires.into_iter().collect::<Vec<_>>();
+    wires.sort_by_key(|w| {
+        let bit = bus_bit(w).map(|(bus, bit)| (bit, bus));
+        (bit.is_none(), bit, w.clone())
+    });
+
+    let (inputs, rest): (Vec<_>, Vec<_>) = wires
+        .into_iter()
+        .partition(|w| !driven.contains(w.as_str()));
+    let (outputs, internal) = rest
+        .into_iter()
+        .partition(|w| matches!(bus_bit(w), Some(('z', _))));
+
+    (inputs, outputs, internal)
+}
+
+// Renders the circuit as a Graphviz digraph, with a box per gate and an ellipse per wire. The inputs
+// sit on the left and the z outputs on the right, both in bit order.
+fn dot(logic: &Logic, highlight: &Highlight) -> String {
+    let (inputs, outputs, internal) = ports(logic);
+
+    let mut out = String::new();
+    writeln!(out, "digraph circuit {{").unwrap();
+    writeln!(out, "  rankdir=LR;").unwrap();
+    writeln!(out, "  node [fontname=\"monospace\"];").unwrap();
+
+    for w in inputs.iter().chain(outputs.iter()).chain(internal.iter()) {
+        let style = if highlight.suspicious.contains(w) {
+            ", style=filled, fillcolor=\"#f4a09c\""
+        } else if highlight.cone.contains(w) {
+            ", style=filled, fillcolor=\"#fbe7a1\""
+        } else {
+            ""
         };
-        println!("{} -> {} [arrowhead=\"{arrowhead}\"];", gate.a, gate.out);
-        println!("{} -> {} [arrowhead=\"{arrowhead}\"];", gate.b, gate.out);
+        writeln!(out, "  \"{w}\" [shape=ellipse{style}];").unwrap();
+    }
+
+    for (i, g) in logic.gates.iter().enumerate() {
+        writeln!(out, "  g{i} [shape=box, label=\"{}\"];", g.op).unwrap();
+        writeln!(out, "  \"{}\" -> g{i};", g.a).unwrap();
+        writeln!(out, "  \"{}\" -> g{i};", g.b).unwrap();
+        writeln!(out, "  g{i} -> \"{}\";", g.out).unwrap();
+    }
+
+    // Pin the inputs and outputs to the edges of the drawing, chained by invisible edges to keep them in order
+    for (rank, group) in [("source", &inputs), ("sink", &outputs)] {
+        if group.is_empty() {
+            continue;
+        }
+        let names = group.iter().map(|w| format!("\"{w}\"")).collect::<Vec<_>>();
+        writeln!(out, "  {{ rank={rank}; {}; }}", names.join("; ")).unwrap();
+        writeln!(out, "  {} [style=invis];", names.join(" -> ")).unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+const VERILOG_KEYWORDS: &str = "\
+    always and assign begin buf bufif0 bufif1 case casex casez cmos deassign default defparam \
+    disable edge else end endcase endfunction endmodule endprimitive endspecify endtable endtask \
+    event for force forever fork function highz0 highz1 if ifnone initial inout input integer \
+    join large macromodule medium module nand negedge nmos nor not notif0 notif1 or output \
+    parameter pmos posedge primitive pull0 pull1 pulldown pullup rcmos real realtime reg release \
+    repeat rnmos rpmos rtran rtranif0 rtranif1 scalared small specify specparam strong0 strong1 \
+    supply0 supply1 table task time tran tranif0 tranif1 tri tri0 tri1 triand trior trireg \
+    vectored wait wand weak0 weak1 while wire wor xnor xor";
+
+fn plain_verilog_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !VERILOG_KEYWORDS.split_whitespace().any(|k| k == name)
+}
+
+// Wire names that aren't plain Verilog identifiers, keywords included, are written as escaped
+// identifiers: a backslash, the name, and a space to end it
+fn verilog_name(name: &str) -> String {
+    if plain_verilog_name(name) {
+        name.to_string()
+    } else {
+        format!("\\{name} ")
+    }
+}
+
+// Structural Verilog, one gate primitive per gate. The gates are left unnamed, which Verilog allows
+// for primitives, so they can't clash with a wire.
+fn to_verilog(logic: &Logic) -> String {
+    let (inputs, outputs, internal) = ports(logic);
+    let names = |wires: &[String]| {
+        wires
+            .iter()
+            .map(|w| verilog_name(w))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "module circuit({});",
+        names(&[inputs.clone(), outputs.clone()].concat())
+    )
+    .unwrap();
+    for (kind, wires) in [
+        ("input", &inputs),
+        ("output", &outputs),
+        ("wire", &internal),
+    ] {
+        if !wires.is_empty() {
+            writeln!(out, "  {kind} {};", names(wires)).unwrap();
+        }
+    }
+    for g in logic.gates.iter() {
+        let op = g.op.to_string().to_lowercase();
+        writeln!(
+            out,
+            "  {op} ({});",
+            names(&[g.out.clone(), g.a.clone(), g.b.clone()])
+        )
+        .unwrap();
+    }
+    writeln!(out, "endmodule").unwrap();
+
+    out
+}
+
+// The wire name at the start of `text`, plain or escaped, and whatever follows it
+fn verilog_identifier(text: &str) -> Option<(String, &str)> {
+    let text = text.trim_start();
+    let (name, rest) = match text.strip_prefix('\\') {
+        Some(escaped) => {
+            let end = escaped.find(char::is_whitespace).unwrap_or(escaped.len());
+            escaped.split_at(end)
+        }
+        None => {
+            let end = text
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(text.len());
+            let (name, rest) = text.split_at(end);
+            if !plain_verilog_name(name) {
+                return None;
+            }
+            (name, rest)
+        }
+    };
+    (!name.is_empty()).then(|| (name.to_string(), rest))
+}
+
+// A wire name as written in Verilog, escaped or not, with nothing else around it
+fn verilog_wire(text: &str) -> Result<String, String> {
+    match verilog_identifier(text) {
+        Some((name, rest)) if rest.trim().is_empty() => Ok(name),
+        _ => Err(format!("`{}` is not a wire name", text.trim())),
+    }
+}
+
+// Two wires with a single &, | or ^ between them
+fn verilog_expression(expr: &str) -> Option<(Op, String, String)> {
+    let (a, rest) = verilog_identifier(expr)?;
+    let rest = rest.trim_start();
+    let op = match rest.chars().next()? {
+        '&' => Op::And,
+        '|' => Op::Or,
+        '^' => Op::Xor,
+        _ => return None,
+    };
+    let (b, rest) = verilog_identifier(&rest[1..])?;
+    rest.trim().is_empty().then_some((op, a, b))
+}
+
+// Reads back the subset `to_verilog` writes: and/or/xor primitives, or `assign` with a single &, | or ^.
+// Escaped names can't have a comma, semicolon or bracket in them. Inputs start out as 0.
+fn from_verilog(input: &str) -> Result<Logic, String> {
+    let mut text = String::with_capacity(input.len());
+    let mut rest = input;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("//") {
+            rest = r.split_once('\n').map_or("", |(_, r)| r);
+        } else if let Some(r) = rest.strip_prefix("/*") {
+            rest = r.split_once("*/").ok_or("unterminated comment")?.1;
+        } else {
+            let ch = rest.chars().next().unwrap();
+            text.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        }
+    }
+
+    let mut values = HashMap::new();
+    let mut gates = Vec::new();
+    for statement in text.split(';') {
+        let statement = statement.trim();
+        let statement = statement
+            .strip_prefix("endmodule")
+            .unwrap_or(statement)
+            .trim();
+        let Some((keyword, body)) = statement.split_once(char::is_whitespace) else {
+            if statement.is_empty() {
+                continue;
+            }
+            return Err(format!("unexpected `{statement}`"));
+        };
+        let body = body.trim();
+
+        match keyword {
+            "module" | "output" | "wire" => {}
+            "input" => {
+                for name in body.split(',') {
+                    values.insert(verilog_wire(name)?, false);
+                }
+            }
+            "and" | "or" | "xor" => {
+                let op = match keyword {
+                    "and" => Op::And,
+                    "or" => Op::Or,
+                    _ => Op::Xor,
+                };
+                let ports = body
+                    .split_once('(')
+                    .and_then(|(_, p)| p.strip_suffix(')'))
+                    .ok_or_else(|| format!("expected a port list in `{statement}`"))?
+                    .split(',')
+                    .map(verilog_wire)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let [out, a, b] = <[String; 3]>::try_from(ports)
+                    .map_err(|_| format!("expected 3 ports in `{statement}`"))?;
+                gates.push(Gate { op, a, b, out });
+            }
+            "assign" => {
+                let (out, expr) = body
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected `=` in `{statement}`"))?;
+                let (op, a, b) = verilog_expression(expr).ok_or_else(|| {
+                    format!("expected two wires and a single &, | or ^ in `{statement}`")
+                })?;
+                gates.push(Gate {
+                    op,
+                    a,
+                    b,
+                    out: verilog_wire(out)?,
+                });
+            }
+            _ => return Err(format!("unsupported statement `{statement}`")),
+        }
+    }
+
+    Ok(Logic { values, gates })
+}
+
+// BLIF, with each gate as a two input `.names` cover
+fn to_blif(logic: &Logic) -> String {
+    let (inputs, outputs, _) = ports(logic);
+
+    let mut out = String::new();
+    writeln!(out, ".model circuit").unwrap();
+    writeln!(out, ".inputs {}", inputs.join(" ")).unwrap();
+    writeln!(out, ".outputs {}", outputs.join(" ")).unwrap();
+    for g in logic.gates.iter() {
+        writeln!(out, ".names {} {} {}", g.a, g.b, g.out).unwrap();
+        let cover = match g.op {
+            Op::And => "11 1\n",
+            Op::Or => "1- 1\n-1 1\n",
+            Op::Xor => "10 1\n01 1\n",
+        };
+        out.push_str(cover);
+    }
+    writeln!(out, ".end").unwrap();
+
+    out
+}
+
+// Reads back `.names` blocks with two inputs, whichever way the cover is written, as long as its truth
+// table is an AND, OR or XOR. Inputs start out as 0.
+fn from_blif(input: &str) -> Result<Logic, String> {
+    let mut values = HashMap::new();
+    let mut gates = Vec::new();
+
+    let lines = input
+        .lines()
+        .map(|l| l.split_once('#').map_or(l, |(l, _)| l).trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let mut words = lines[i].split_whitespace();
+        let directive = words.next().unwrap();
+        i += 1;
+
+        match directive {
+            ".model" | ".outputs" | ".end" => {}
+            ".inputs" => {
+                for name in words {
+                    values.insert(name.to_string(), false);
+                }
+            }
+            ".names" => {
+                let [a, b, out] =
+                    <[&str; 3]>::try_from(words.collect::<Vec<_>>()).map_err(|_| {
+                        format!("expected 2 inputs and an output on `{}`", lines[i - 1])
+                    })?;
+
+                // Which of 00, 01, 10, 11 turn the output on
+                let mut table = [false; 4];
+                while i < lines.len() && !lines[i].starts_with('.') {
+                    let Some((pattern, "1")) = lines[i].split_once(' ') else {
+                        return Err(format!("unsupported cover row `{}`", lines[i]));
+                    };
+                    let pattern = pattern.as_bytes();
+                    if pattern.len() != 2 {
+                        return Err(format!("unsupported cover row `{}`", lines[i]));
+                    }
+                    let pattern = pattern
+                        .iter()
+                        .map(|p| match p {
+                            b'0' => Ok(Some(0)),
+                            b'1' => Ok(Some(1)),
+                            b'-' => Ok(None),
+                            _ => Err(format!("unsupported cover row `{}`", lines[i])),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for (row, on) in table.iter_mut().enumerate() {
+                        let bits = [(row >> 1) & 1, row & 1];
+                        if pattern
+                            .iter()
+                            .zip(bits)
+                            .all(|(p, bit)| p.is_none_or(|p| p == bit))
+                        {
+                            *on = true;
+                        }
+                    }
+                    i += 1;
+                }
+
+                let op = match table {
+                    [false, false, false, true] => Op::And,
+                    [false, true, true, true] => Op::Or,
+                    [false, true, true, false] => Op::Xor,
+                    _ => return Err(format!("{out} is not an AND, OR or XOR of {a} and {b}")),
+                };
+                gates.push(Gate {
+                    op,
+                    a: a.to_string(),
+                    b: b.to_string(),
+                    out: out.to_string(),
+                });
+            }
+            _ => return Err(format!("unsupported directive `{directive}`")),
+        }
+    }
+
+    Ok(Logic { values, gates })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gate(op: Op, a: &str, b: &str, out: &str) -> Gate {
+        Gate {
+            op,
+            a: a.to_string(),
+            b: b.to_string(),
+            out: out.to_string(),
+        }
+    }
+
+    // A textbook ripple-carry adder, with x + y as inputs
+    fn adder(bits: usize, x: u64, y: u64) -> Logic {
+        let mut values = HashMap::new();
+        let mut gates = Vec::new();
+        for n in 0..bits {
+            let (xn, yn, zn) = (format!("x{n:02}"), format!("y{n:02}"), format!("z{n:02}"));
+            values.insert(xn.clone(), x & (1 << n) != 0);
+            values.insert(yn.clone(), y & (1 << n) != 0);
+
+            let carry_in = format!("c{:02}", n.wrapping_sub(1));
+            let carry_out = if n == bits - 1 {
+                format!("z{bits:02}")
+            } else {
+                format!("c{n:02}")
+            };
+            if n == 0 {
+                gates.push(gate(Op::Xor, &xn, &yn, &zn));
+                gates.push(gate(Op::And, &xn, &yn, &carry_out));
+            } else {
+                let (s, h, t) = (format!("s{n:02}"), format!("h{n:02}"), format!("t{n:02}"));
+                gates.push(gate(Op::Xor, &xn, &yn, &s));
+                gates.push(gate(Op::And, &yn, &xn, &h));
+                gates.push(gate(Op::Xor, &carry_in, &s, &zn));
+                gates.push(gate(Op::And, &s, &carry_in, &t));
+                gates.push(gate(Op::Or, &h, &t, &carry_out));
+            }
+        }
+
+        Logic { values, gates }
+    }
+
+    #[test]
+    fn test_adder() {
+        let logic = adder(8, 173, 99);
+        assert_eq!(Ok(()), verify(&logic));
+        assert_eq!(272, part1(logic));
+    }
+
+    #[test]
+    fn test_netlist_lanes() {
+        let netlist = Netlist::compile(&adder(10, 0, 0)).unwrap();
+        assert_eq!(
+            vec![0, 2, 1023, 2046, 1234],
+            netlist.add(&[(0, 0), (1, 1), (1000, 23), (1023, 1023), (617, 617)])
+        );
+        assert!(adds_correctly(&netlist, 4, 42));
+
+        let mut logic = adder(10, 0, 0);
+        logic.swap("z06", "t06");
+        assert!(!adds_correctly(&Netlist::compile(&logic).unwrap(), 4, 42));
+    }
+
+    #[test]
+    fn test_netlist_cycle() {
+        let mut logic = adder(4, 0, 0);
+        logic.swap("s02", "c02");
+
+        let Err(CompileError::Cycle(cycle)) = Netlist::compile(&logic) else {
+            panic!("expected a cycle");
+        };
+        assert_eq!(2, cycle.0.len());
+        for wire in ["s02", "t02"] {
+            assert!(cycle.0.contains(&wire.to_string()), "{cycle}");
+        }
+    }
+
+    #[test]
+    fn test_netlist_drivers() {
+        let mut logic = adder(4, 0, 0);
+        logic.gates.push(gate(Op::Or, "x01", "y02", "t02"));
+        assert_eq!(
+            Err(CompileError::MultipleDrivers("t02".to_string())),
+            Netlist::compile(&logic).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_netlist_wide() {
+        // The sum of two 64-bit numbers takes 65 bits
+        let netlist = Netlist::compile(&adder(64, 0, 0)).unwrap();
+        assert_eq!(
+            vec![u64::MAX as u128 * 2],
+            netlist.add(&[(u64::MAX, u64::MAX)])
+        );
+        assert!(adds_correctly(&netlist, 2, 7));
+    }
+
+    #[test]
+    fn test_dot() {
+        let logic = adder(2, 0, 0);
+        let highlight = Highlight {
+            suspicious: HashSet::from(["t01".to_string()]),
+            cone: {
+                let netlist = Netlist::compile(&logic).unwrap();
+                netlist
+                    .names(&netlist.cone(netlist.id("z01").unwrap()))
+                    .into_iter()
+                    .collect()
+            },
+        };
+        let dot = dot(&logic, &highlight);
+
+        assert!(dot.starts_with("digraph circuit {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("  g0 [shape=box, label=\"XOR\"];\n  \"x00\" -> g0;\n  \"y00\" -> g0;\n  g0 -> \"z00\";\n"));
+        assert!(dot.contains("  { rank=source; \"x00\"; \"y00\"; \"x01\"; \"y01\"; }\n"));
+        assert!(dot.contains("  { rank=sink; \"z00\"; \"z01\"; \"z02\"; }\n"));
+        assert!(dot.contains("  \"t01\" [shape=ellipse, style=filled, fillcolor=\"#f4a09c\"];\n"));
+        assert!(dot.contains("  \"c00\" [shape=ellipse, style=filled, fillcolor=\"#fbe7a1\"];\n"));
+        assert!(dot.contains("  \"h01\" [shape=ellipse];\n"));
+    }
+
+    #[test]
+    fn test_ports() {
+        // Bits numbered past 99 and written with different widths, and an internal wire that only
+        // looks like an input
+        let logic = Logic {
+            values: HashMap::from([
+                ("x2".to_string(), false),
+                ("y2".to_string(), false),
+                ("x10".to_string(), false),
+                ("y10".to_string(), false),
+            ]),
+            gates: vec![
+                gate(Op::And, "x10", "y10", "z10"),
+                gate(Op::Xor, "x2", "y2", "z2"),
+                gate(Op::Or, "z2", "z10", "xyz"),
+                gate(Op::And, "xyz", "x2", "z100"),
+            ],
+        };
+        let strings = |wires: &[&str]| wires.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            (
+                strings(&["x2", "y2", "x10", "y10"]),
+                strings(&["z2", "z10", "z100"]),
+                strings(&["xyz"])
+            ),
+            ports(&logic)
+        );
+
+        let dot = dot(&logic, &Highlight::default());
+        assert!(dot.contains("  { rank=source; \"x2\"; \"y2\"; \"x10\"; \"y10\"; }\n"));
+        assert!(dot.contains("  { rank=sink; \"z2\"; \"z10\"; \"z100\"; }\n"));
+    }
+
+    fn same_gates(a: &Logic, b: &Logic) -> bool {
+        let gates = |logic: &Logic| {
+            let mut gates = logic
+                .gates
+                .iter()
+                .map(|g| {
+                    let (a, b) = sorted(&g.a, &g.b);
+                    format!("{a} {} {b} -> {}", g.op, g.out)
+                })
+                .collect::<Vec<_>>();
+            gates.sort();
+            gates
+        };
+
+        gates(a) == gates(b)
+    }
+
+    #[test]
+    fn test_verilog() {
+        let logic = adder(4, 0, 0);
+        let verilog = to_verilog(&logic);
+        assert!(verilog.starts_with(
+            "module circuit(x00, y00, x01, y01, x02, y02, x03, y03, z00, z01, z02, z03, z04);\n"
+        ));
+        assert!(verilog.contains("  xor (z00, x00, y00);\n"));
+
+        let imported = from_verilog(&verilog).unwrap();
+        assert!(same_gates(&logic, &imported));
+        assert_eq!(4, input_bits(&imported));
+        assert_eq!(Ok(()), verify(&imported));
+
+        let handwritten = "
+            // half adder
+            module half(x00, y00, z00, z01);
+              input x00, y00; output z00, z01;
+              /* the sum */ assign z00 = x00 ^ y00;
+              and carry(z01, y00, x00);
+            endmodule
+        ";
+        let imported = from_verilog(handwritten).unwrap();
+        assert_eq!(Ok(()), verify(&imported));
+        assert!(from_verilog("module m(a); nand g(a, b, c); endmodule").is_err());
+        assert!(from_verilog("module m(a); and g(a, , c); endmodule").is_err());
+
+        // Only one operator between two wires, nothing else
+        for expr in ["a & b & c", "~a & b", "(a | b)", "a &", "a b", "\\ & b"] {
+            let verilog = format!("module m(z); assign z = {expr}; endmodule");
+            assert!(from_verilog(&verilog).is_err(), "{expr}");
+        }
+        let imported = from_verilog("module m(z); assign z = \\a&b  ^ c$1; endmodule").unwrap();
+        assert_eq!(vec![gate(Op::Xor, "a&b", "c$1", "z")], imported.gates);
+
+        // Wires named like keywords, or not like identifiers at all
+        let logic = Logic {
+            values: HashMap::from([("x00".to_string(), false), ("y00".to_string(), false)]),
+            gates: vec![
+                gate(Op::And, "x00", "y00", "and"),
+                gate(Op::Or, "and", "x00", "1st"),
+                gate(Op::Xor, "1st", "and", "z00"),
+                gate(Op::Xor, "z00", "y00", ""),
+            ],
+        };
+        let verilog = to_verilog(&logic);
+        assert!(verilog.contains("  and (\\and , x00, y00);\n"));
+        assert!(verilog.contains("  or (\\1st , \\and , x00);\n"));
+        assert!(from_verilog(&verilog).is_err());
+
+        let logic = Logic {
+            values: logic.values,
+            gates: logic.gates[..3].to_vec(),
+        };
+        let imported = from_verilog(&to_verilog(&logic)).unwrap();
+        assert!(same_gates(&logic, &imported));
+    }
+
+    #[test]
+    fn test_internal_wire_names() {
+        // A carry named like an input is still driven by a gate, so it's declared as a wire
+        let mut logic = adder(2, 0, 0);
+        for g in logic.gates.iter_mut() {
+            for wire in [&mut g.a, &mut g.b, &mut g.out] {
+                if wire == "c00" {
+                    *wire = "xyz".to_string();
+                }
+            }
+        }
+        assert_eq!(2, input_bits(&logic));
+
+        let verilog = to_verilog(&logic);
+        assert!(
+            verilog.contains("  input x00, y00, x01, y01;\n"),
+            "{verilog}"
+        );
+        assert!(
+            verilog.contains("  wire h01, s01, t01, xyz;\n"),
+            "{verilog}"
+        );
+        assert!(to_blif(&logic).contains(".inputs x00 y00 x01 y01\n"));
+    }
+
+    #[test]
+    fn test_blif() {
+        let logic = adder(4, 0, 0);
+        let blif = to_blif(&logic);
+        assert!(blif.contains(".names x00 y00 z00\n10 1\n01 1\n"));
+
+        let imported = from_blif(&blif).unwrap();
+        assert!(same_gates(&logic, &imported));
+        assert_eq!(Ok(()), verify(&imported));
+
+        let handwritten = "
+            .model half # a half adder
+            .inputs x00 y00
+            .outputs z00 z01
+            .names x00 y00 z00
+            01 1
+            10 1
+            .names x00 y00 z01
+            11 1
+            .names z00 z01 unused
+            11 1
+            10 1
+            01 1
+            .end
+        ";
+        let imported = from_blif(handwritten).unwrap();
+        assert_eq!(Op::Or, imported.gates[2].op);
+        assert_eq!(Ok(()), verify(&imported));
+        assert!(from_blif(".names a b c\n00 1\n").is_err());
+        assert!(from_blif(".names a b c\n+1 1\n").is_err());
+        assert!(from_blif(".names a b c\n1  1\n").is_err());
+    }
+
+    #[test]
+    fn test_cone() {
+        let netlist = Netlist::compile(&adder(4, 0, 0)).unwrap();
+        let id = |w: &str| netlist.id(w).unwrap();
+        let names = |ids: Vec<usize>| {
+            let mut names = netlist.names(&ids);
+            names.sort();
+            names
+        };
+
+        assert_eq!(vec!["c00", "s01"], names(netlist.fan_in(id("z01"))));
+        assert_eq!(vec!["t01", "z01"], names(netlist.fan_out(id("s01"))));
+        assert_eq!(
+            vec!["c00", "s01", "x00", "x01", "y00", "y01", "z01"],
+            names(netlist.cone(id("z01")))
+        );
+        assert_eq!(
+            vec!["z03", "z04"],
+            netlist.names(&netlist.affected_outputs(id("h02")))
+        );
+        assert!(netlist.affected_outputs(id("z04")).len() == 1);
+    }
+
+    #[test]
+    fn test_equivalent() {
+        let mut logic = adder(10, 0, 0);
+        logic.gates.push(gate(Op::Xor, "x01", "c00", "p01"));
+        logic.gates.push(gate(Op::Xor, "p01", "y01", "q01"));
+        let netlist = Netlist::compile(&logic).unwrap();
+        let id = |w: &str| netlist.id(w).unwrap();
+
+        assert_eq!(
+            Equivalence::Proven,
+            netlist.equivalent(id("z01"), id("q01"), 4, 1)
+        );
+        let Equivalence::Differs(assignment) = netlist.equivalent(id("s01"), id("h01"), 4, 1)
+        else {
+            panic!("XOR and AND are not the same");
+        };
+        assert_eq!(2, assignment.len());
+
+        // z09 depends on all 20 inputs, which is too many to try them all
+        assert_eq!(
+            Equivalence::Likely,
+            netlist.equivalent(id("z09"), id("z09"), 4, 1)
+        );
+        assert!(matches!(
+            netlist.equivalent(id("z09"), id("z08"), 4, 1),
+            Equivalence::Differs(_)
+        ));
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut logic = adder(8, 0, 0);
+        logic.swap("z03", "t03");
+
+        let fault = verify(&logic).unwrap_err();
+        assert_eq!(3, fault.bit);
+        assert_eq!(Some("c02".to_string()), fault.carry);
+        assert_eq!(
+            FaultKind::WrongOutput {
+                expected: "z03".to_string(),
+                found: "t03".to_string()
+            },
+            fault.kind
+        );
+    }
+
+    #[test]
+    fn test_faults() {
+        let mut logic = adder(12, 0, 0);
+        logic.swap("z02", "c02");
+        logic.swap("z09", "t09");
+        logic.swap("c10", "z11");
+
+        let bits = faults(&logic).iter().map(|f| f.bit).collect::<Vec<_>>();
+        assert_eq!(vec![2, 9, 11], bits);
+        assert_eq!(Err(faults(&logic)[0].clone()), verify(&logic));
+        assert!(faults(&adder(12, 0, 0)).is_empty());
+
+        // The last carry has to come out on the top z bit
+        let mut logic = adder(6, 0, 0);
+        logic.gates.iter_mut().find(|g| g.out == "z06").unwrap().out = "c05".to_string();
+        let faults = faults(&logic);
+        assert_eq!(1, faults.len());
+        assert_eq!(
+            "bit 6: expected the result on z06, found it on c05",
+            faults[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut logic = adder(12, 0, 0);
+        logic.swap("z02", "c02");
+        logic.swap("s05", "h05");
+        logic.swap("z09", "t09");
+        logic.swap("c10", "z11");
+
+        let swaps = repair(&logic, 4).unwrap();
+        assert_eq!(4, swaps.len());
+        assert_eq!("c02,c10,h05,s05,t09,z02,z09,z11", part2(logic));
+    }
+
+    #[test]
+    fn test_repair_minimal() {
+        let mut logic = adder(6, 0, 0);
+        logic.swap("z04", "c04");
+
+        assert_eq!(None, repair(&logic, 0));
+        assert_eq!(1, repair(&logic, 4).unwrap().len());
     }
 }
 